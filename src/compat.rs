@@ -0,0 +1,86 @@
+//! The zellij-tile calls whose signature or meaning changed between zellij releases, in one
+//! place so supporting another release only needs changes here. Only zellij-tile 0.40, the
+//! version in `Cargo.toml`, is supported: there are no feature flags selecting another version,
+//! as releases before 0.40 lack plugin pipes altogether. Instead the build checks the
+//! signatures the wrappers rely on, so an upgrade that changes one fails here rather than at the
+//! call sites.
+
+use std::collections::BTreeMap;
+
+use zellij_tile::prelude::*;
+
+use crate::persistence::CONTEXT_KEY;
+
+const CLIPBOARD: &str = "clipboard";
+/// Context key holding what `copy_to_clipboard` copied, e.g. `command`.
+const WHAT_KEY: &str = "copied";
+
+const _: fn(u32, bool) = focus_terminal_pane;
+const _: fn(u32) = go_to_tab;
+const _: fn(&[&str], BTreeMap<String, String>) = run_command;
+const _: fn(u32, &'static str) = rename_terminal_pane::<&'static str>;
+
+/// Focus the terminal pane `pane_id`, switching to its tab. `float_if_hidden` decides whether
+/// hidden floating panes are shown when the pane is one of them.
+pub fn focus_pane(pane_id: u32, float_if_hidden: bool) {
+    focus_terminal_pane(pane_id, float_if_hidden);
+}
+
+/// Switch to the tab at `position`, as found in `TabInfo::position`.
+pub fn switch_to_tab(position: usize) {
+    go_to_tab(position as u32);
+}
+
+/// Put `name` in the frame of the terminal pane `pane_id`. zellij-tile 0.40 has no call to
+/// highlight a pane, so naming it is the only way to point at it without moving the focus.
+pub fn mark_pane(pane_id: u32, name: &str) {
+    rename_terminal_pane(pane_id, name);
+}
+
+/// Undo `mark_pane`: without a name the frame shows the pane's own title again.
+pub fn unmark_pane(pane_id: u32) {
+    rename_terminal_pane(pane_id, "");
+}
+
+/// Copy `text` to the clipboard of the machine zellij runs on. zellij-tile 0.40 has no clipboard
+/// call, so the first clipboard tool found is run, which requires the permission to run commands.
+/// `what` names the copied text in the result, see `clipboard_result`.
+pub fn copy_to_clipboard(text: &str, what: &str) {
+    let script =
+        r#"printf '%s' "$1" | { wl-copy || xclip -selection clipboard || pbcopy; } 2>/dev/null"#;
+    run_command(
+        &["sh", "-c", script, "harpoon", text],
+        clipboard_context(what),
+    );
+}
+
+fn clipboard_context(what: &str) -> BTreeMap<String, String> {
+    BTreeMap::from([
+        (CONTEXT_KEY.to_string(), CLIPBOARD.to_string()),
+        (WHAT_KEY.to_string(), what.to_string()),
+    ])
+}
+
+/// What was copied, if a `RunCommandResult` is that of `copy_to_clipboard`. A non-zero exit code
+/// means no clipboard tool was found or it failed, e.g. without a display.
+pub fn clipboard_result(context: &BTreeMap<String, String>) -> Option<&str> {
+    if context.get(CONTEXT_KEY).map(String::as_str) != Some(CLIPBOARD) {
+        return None;
+    }
+    Some(context.get(WHAT_KEY).map_or("text", String::as_str))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clipboard_results_name_what_was_copied() {
+        assert_eq!(
+            clipboard_result(&clipboard_context("command")),
+            Some("command")
+        );
+        let other = BTreeMap::from([(CONTEXT_KEY.to_string(), "save".to_string())]);
+        assert_eq!(clipboard_result(&other), None);
+    }
+}
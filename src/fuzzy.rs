@@ -0,0 +1,79 @@
+/// Bonus for every matched character.
+const MATCH_SCORE: i64 = 1;
+/// Bonus for a character matched right after the previous one.
+const CONSECUTIVE_BONUS: i64 = 5;
+/// Bonus for a character matched at the start of a word.
+const WORD_START_BONUS: i64 = 3;
+/// Bonus when the query appears as is, so plain substring matches rank first.
+const SUBSTRING_BONUS: i64 = 20;
+
+/// Match `query` as a case-insensitive subsequence of `candidate`, e.g. `nvm` matches `nvim`.
+/// Returns the score of the match, higher being better, and the char positions of `candidate`
+/// that matched. An empty query matches everything with a score of 0.
+pub fn fuzzy_match(query: &str, candidate: &str) -> Option<(i64, Vec<usize>)> {
+    let query: Vec<char> = query.to_lowercase().chars().collect();
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut positions = Vec::with_capacity(query.len());
+    let mut query_chars = query.iter().peekable();
+    for (idx, c) in candidate.iter().enumerate() {
+        let Some(&&q) = query_chars.peek() else {
+            break;
+        };
+        if *c != q {
+            continue;
+        }
+        score += MATCH_SCORE;
+        if idx > 0 && positions.last() == Some(&(idx - 1)) {
+            score += CONSECUTIVE_BONUS;
+        }
+        if idx == 0 || is_separator(candidate[idx - 1]) {
+            score += WORD_START_BONUS;
+        }
+        positions.push(idx);
+        query_chars.next();
+    }
+    if query_chars.peek().is_some() {
+        return None;
+    }
+    if !query.is_empty() && contains(&candidate, &query) {
+        score += SUBSTRING_BONUS;
+    }
+    Some((score, positions))
+}
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '|' | '-' | '_' | '/' | '.')
+}
+
+fn contains(haystack: &[char], needle: &[char]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(query: &str, candidate: &str) -> i64 {
+        fuzzy_match(query, candidate).unwrap().0
+    }
+
+    #[test]
+    fn matches_subsequence_ignoring_case() {
+        assert_eq!(fuzzy_match("nvm", "NVim"), Some((11, vec![0, 1, 3])));
+        assert_eq!(fuzzy_match("vn", "nvim"), None);
+        assert_eq!(fuzzy_match("", "nvim"), Some((0, vec![])));
+    }
+
+    #[test]
+    fn substring_ranks_above_scattered_match() {
+        assert!(score("vim", "nvim") > score("vim", "vi-m"));
+    }
+
+    #[test]
+    fn word_starts_rank_above_middles() {
+        assert!(score("b", "my build") > score("b", "debug"));
+    }
+}
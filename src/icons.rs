@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+
+use zellij_tile::prelude::*;
+
+/// Nerd Font icons shown before bookmarks, by the command running in the pane.
+pub struct Icons {
+    /// Command names and their icon, checked in order.
+    commands: Vec<(String, String)>,
+}
+
+impl Default for Icons {
+    fn default() -> Self {
+        let commands = [
+            ("nvim", "\u{e62b}"),
+            ("vim", "\u{e62b}"),
+            ("hx", "\u{f044}"),
+            ("bash", "\u{f489}"),
+            ("zsh", "\u{f489}"),
+            ("fish", "\u{f489}"),
+            ("lazygit", "\u{e702}"),
+            ("git", "\u{e702}"),
+            ("cargo", "\u{e7a8}"),
+            ("python", "\u{e73c}"),
+            ("node", "\u{e718}"),
+            ("htop", "\u{f080}"),
+        ];
+        Icons {
+            commands: commands
+                .into_iter()
+                .map(|(command, icon)| (command.to_string(), icon.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl Icons {
+    /// Build the icons from the plugin configuration. `icon_map` takes a comma separated list of
+    /// `command=icon` pairs, which take precedence over the default icons. Pairs that can't be
+    /// parsed are reported in `errors`.
+    pub fn from_configuration(
+        configuration: &BTreeMap<String, String>,
+        errors: &mut Vec<String>,
+    ) -> Self {
+        let mut icons = Icons::default();
+        let Some(value) = configuration.get("icon_map") else {
+            return icons;
+        };
+        let mut commands = Vec::new();
+        for pair in value.split(',') {
+            match pair.split_once('=') {
+                Some((command, icon)) if !command.trim().is_empty() => {
+                    commands.push((command.trim().to_string(), icon.trim().to_string()))
+                }
+                _ => errors.push(format!("Invalid icon_map: '{}'", pair.trim())),
+            }
+        }
+        commands.append(&mut icons.commands);
+        icons.commands = commands;
+        icons
+    }
+
+    /// The icon of the command a pane runs, detected from its command or else its title.
+    pub fn icon(&self, pane_info: &PaneInfo) -> Option<&str> {
+        let command = pane_info
+            .terminal_command
+            .as_deref()
+            .unwrap_or(&pane_info.title);
+        let name = command_name(command)?;
+        self.commands
+            .iter()
+            .find(|(command, _)| *command == name)
+            .map(|(_, icon)| icon.as_str())
+    }
+}
+
+/// The program of a command line, e.g. `nvim` of `/usr/bin/nvim src/main.rs`.
+fn command_name(command: &str) -> Option<&str> {
+    let program = command.split_whitespace().next()?;
+    program.rsplit('/').next()
+}
@@ -0,0 +1,491 @@
+use std::collections::BTreeMap;
+
+use zellij_tile::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Add,
+    AddAll,
+    Delete,
+    Compact,
+    Pin,
+    Quit,
+    Down,
+    Up,
+    PageDown,
+    PageUp,
+    Focus,
+    GoToTab,
+    Browse,
+    Search,
+    MoveDown,
+    MoveUp,
+    MoveToTop,
+    MoveToBottom,
+    Duplicate,
+    Lock,
+    Help,
+    Swap,
+    CycleSort,
+    TogglePersist,
+    TypeAhead,
+    Reload,
+    AddMode,
+    Note,
+    Mark,
+    DeleteMarked,
+    CopyCommand,
+    PinAll,
+    ToggleIds,
+    Back,
+    Details,
+}
+
+impl Action {
+    /// Whether the action changes the list, which isn't allowed while it is locked.
+    pub fn is_mutating(&self) -> bool {
+        matches!(
+            self,
+            Action::Add
+                | Action::AddAll
+                | Action::Delete
+                | Action::Compact
+                | Action::Pin
+                | Action::PinAll
+                | Action::MoveDown
+                | Action::MoveUp
+                | Action::MoveToTop
+                | Action::MoveToBottom
+                | Action::Duplicate
+                | Action::Swap
+                | Action::TogglePersist
+                | Action::Reload
+                | Action::AddMode
+                | Action::Note
+                | Action::DeleteMarked
+                | Action::CycleSort
+        )
+    }
+
+    /// Whether the action works on the bookmarks already listed, so it does nothing on an empty
+    /// list. Adding panes, help, browsing, settings and quitting still work then.
+    pub fn needs_bookmarks(&self) -> bool {
+        matches!(
+            self,
+            Action::Delete
+                | Action::Compact
+                | Action::Pin
+                | Action::Down
+                | Action::Up
+                | Action::PageDown
+                | Action::PageUp
+                | Action::Focus
+                | Action::GoToTab
+                | Action::Search
+                | Action::MoveDown
+                | Action::MoveUp
+                | Action::MoveToTop
+                | Action::MoveToBottom
+                | Action::Duplicate
+                | Action::Swap
+                | Action::TogglePersist
+                | Action::TypeAhead
+                | Action::Note
+                | Action::Mark
+                | Action::DeleteMarked
+                | Action::CopyCommand
+                | Action::PinAll
+                | Action::Details
+        )
+    }
+
+    /// Whether the action works on the selected bookmark, so it does nothing while the selected
+    /// bookmark isn't shown, e.g. when a search matches nothing.
+    pub fn needs_selection(&self) -> bool {
+        matches!(
+            self,
+            Action::Delete
+                | Action::Pin
+                | Action::Focus
+                | Action::GoToTab
+                | Action::MoveDown
+                | Action::MoveUp
+                | Action::MoveToTop
+                | Action::MoveToBottom
+                | Action::Duplicate
+                | Action::Swap
+                | Action::TogglePersist
+                | Action::Note
+                | Action::Mark
+                | Action::CopyCommand
+                | Action::Details
+        )
+    }
+
+    /// What the action does, as shown in the help overlay.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::Add => "Add the focused pane",
+            Action::AddAll => "Add all panes",
+            Action::Delete => "Remove the selected pane",
+            Action::Compact => "Remove closed panes",
+            Action::Pin => "Pin or unpin the selected pane",
+            Action::Quit => "Exit",
+            Action::Down => "Select the next pane",
+            Action::Up => "Select the previous pane",
+            Action::PageDown => "Move a page down",
+            Action::PageUp => "Move a page up",
+            Action::Focus => "Switch to the selected pane",
+            Action::GoToTab => "Switch to the tab of the selected pane",
+            Action::Browse => "Browse all panes",
+            Action::Search => "Filter the list",
+            Action::MoveDown => "Move the selected pane down",
+            Action::MoveUp => "Move the selected pane up",
+            Action::MoveToTop => "Move the selected pane to the top",
+            Action::MoveToBottom => "Move the selected pane to the bottom",
+            Action::Duplicate => "Duplicate the selected pane",
+            Action::Lock => "Lock or unlock the list",
+            Action::Help => "Show or hide this help",
+            Action::Swap => "Replace the selected pane by the focused pane",
+            Action::CycleSort => "Switch to the next sort mode",
+            Action::TogglePersist => "Save the selected pane to disk or not",
+            Action::TypeAhead => "Go to the next pane starting with the next key typed",
+            Action::Reload => "Add new panes of the saved bookmark file",
+            Action::AddMode => "Add several panes in a row, until Esc",
+            Action::Note => "Write a note on the selected pane",
+            Action::Mark => "Mark or unmark the selected pane",
+            Action::DeleteMarked => "Remove the marked panes",
+            Action::CopyCommand => "Copy the command of the selected pane",
+            Action::PinAll => "Pin all panes, or unpin them if all are pinned",
+            Action::ToggleIds => "Show or hide pane ids",
+            Action::Back => "Like Esc, or select the previous pane along the bar",
+            Action::Details => "Show or hide the details of the selected pane",
+        }
+    }
+
+    /// All actions, together with the config key used to rebind them and their default keys.
+    pub fn all() -> Vec<(Action, &'static str, Vec<Key>)> {
+        vec![
+            (Action::Add, "add_key", vec![Key::Char('a')]),
+            (Action::AddAll, "add_all_key", vec![Key::Char('A')]),
+            (Action::Delete, "delete_key", vec![Key::Char('d')]),
+            (Action::Compact, "compact_key", vec![Key::Char('c')]),
+            (Action::Pin, "pin_key", vec![Key::Char('P')]),
+            (Action::Quit, "quit_key", vec![Key::Esc, Key::Ctrl('c')]),
+            (
+                Action::Down,
+                "down_key",
+                vec![Key::Down, Key::Char('j'), Key::Char('\t')],
+            ),
+            (
+                Action::Up,
+                "up_key",
+                vec![Key::Up, Key::Char('k'), Key::BackTab],
+            ),
+            (Action::PageDown, "page_down_key", vec![Key::PageDown]),
+            (Action::PageUp, "page_up_key", vec![Key::PageUp]),
+            (
+                Action::Focus,
+                "focus_key",
+                vec![Key::Char('\n'), Key::Char('l')],
+            ),
+            (Action::GoToTab, "go_to_tab_key", vec![Key::Char('T')]),
+            (Action::Browse, "browse_key", vec![Key::Char('b')]),
+            (Action::Search, "search_key", vec![Key::Char('/')]),
+            (Action::MoveDown, "move_down_key", vec![Key::Char('J')]),
+            (Action::MoveUp, "move_up_key", vec![Key::Char('K')]),
+            (Action::MoveToTop, "move_to_top_key", vec![Key::Char('t')]),
+            (
+                Action::MoveToBottom,
+                "move_to_bottom_key",
+                vec![Key::Char('B')],
+            ),
+            (Action::Duplicate, "duplicate_key", vec![Key::Char('y')]),
+            (Action::Lock, "lock_key", vec![Key::Char('L')]),
+            (Action::Help, "help_key", vec![Key::Char('?')]),
+            (Action::Swap, "swap_key", vec![Key::Char('s')]),
+            (Action::CycleSort, "cycle_sort_key", vec![Key::Char('S')]),
+            (Action::TogglePersist, "persist_key", vec![Key::Char('*')]),
+            (Action::TypeAhead, "type_ahead_key", vec![Key::Char('f')]),
+            (Action::Reload, "reload_key", vec![Key::Char('R')]),
+            (Action::AddMode, "add_mode_key", vec![Key::Char('+')]),
+            (Action::Note, "note_key", vec![Key::Char('n')]),
+            (Action::Mark, "mark_key", vec![Key::Char('v')]),
+            (
+                Action::DeleteMarked,
+                "delete_marked_key",
+                vec![Key::Char('D')],
+            ),
+            (
+                Action::CopyCommand,
+                "copy_command_key",
+                vec![Key::Char('Y')],
+            ),
+            (
+                Action::PinAll,
+                "pin_all_key",
+                vec![Key::Alt(CharOrArrow::Char('p'))],
+            ),
+            (Action::ToggleIds, "toggle_ids_key", vec![Key::Char('I')]),
+            (Action::Back, "back_key", vec![Key::Char('h')]),
+            (Action::Details, "details_key", vec![Key::Char('i')]),
+        ]
+    }
+}
+
+pub struct Keymap {
+    bindings: Vec<(Action, Vec<Key>)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap {
+            bindings: Action::all()
+                .into_iter()
+                .map(|(action, _, keys)| (action, keys))
+                .collect(),
+        }
+    }
+}
+
+impl Keymap {
+    /// Build the keymap from the plugin configuration. Each `<action>_key` option takes a comma
+    /// separated list of keys replacing the defaults of that action. Actions whose option can't
+    /// be parsed keep their defaults and the problem is reported in `errors`. So do actions given
+    /// a key of another action, which only the first of them would ever get.
+    pub fn from_configuration(
+        configuration: &BTreeMap<String, String>,
+        errors: &mut Vec<String>,
+    ) -> Self {
+        let defaults = Action::all();
+        let mut configured = Vec::new();
+        let mut bindings: Vec<(Action, Vec<Key>)> = defaults
+            .iter()
+            .map(|(action, config_key, default)| {
+                let keys = match configuration.get(*config_key).map(|v| parse_keys(v)) {
+                    Some(Ok(keys)) => keys,
+                    Some(Err(binding)) => {
+                        errors.push(format!("Invalid {}: '{}'", config_key, binding));
+                        default.clone()
+                    }
+                    None => default.clone(),
+                };
+                configured.push(keys != *default);
+                (*action, keys)
+            })
+            .collect();
+        // Falling back to the defaults can clash with another configured action in turn, so
+        // look again until no configured action shares a key.
+        while let Some((idx, other, key)) = conflict(&bindings, &configured) {
+            errors.push(format!(
+                "{} '{}' is also bound by {}",
+                defaults[idx].1,
+                format_key(&key),
+                defaults[other].1
+            ));
+            bindings[idx].1 = defaults[idx].2.clone();
+            configured[idx] = false;
+        }
+        Keymap { bindings }
+    }
+
+    pub fn bindings(&self) -> &[(Action, Vec<Key>)] {
+        &self.bindings
+    }
+
+    pub fn action(&self, key: &Key) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_, keys)| keys.contains(key))
+            .map(|(action, _)| *action)
+    }
+}
+
+/// The first key of a configured binding that another binding has too, with the indices of both
+/// bindings.
+fn conflict(bindings: &[(Action, Vec<Key>)], configured: &[bool]) -> Option<(usize, usize, Key)> {
+    bindings
+        .iter()
+        .enumerate()
+        .filter(|&(idx, _)| configured[idx])
+        .find_map(|(idx, (_, keys))| {
+            bindings
+                .iter()
+                .enumerate()
+                .filter(|&(other, _)| other != idx)
+                .find_map(|(other, (_, other_keys))| {
+                    let key = keys.iter().find(|key| other_keys.contains(key))?;
+                    Some((idx, other, *key))
+                })
+        })
+}
+
+/// Parse a comma separated list of keys, returning the first binding that failed to parse.
+fn parse_keys(value: &str) -> Result<Vec<Key>, String> {
+    value
+        .split(',')
+        .map(|binding| parse_key(binding).ok_or_else(|| binding.trim().to_string()))
+        .collect()
+}
+
+/// Parse a single key such as `d`, `ctrl+d`, `alt+j`, `alt+up`, `esc` or `enter`.
+pub fn parse_key(binding: &str) -> Option<Key> {
+    let binding = binding.trim();
+    if let Some(rest) = strip_modifier(binding, "ctrl") {
+        return single_char(rest).map(Key::Ctrl);
+    }
+    if let Some(rest) = strip_modifier(binding, "alt") {
+        if let Some(direction) = parse_direction(rest) {
+            return Some(Key::Alt(CharOrArrow::Direction(direction)));
+        }
+        return single_char(rest).map(|c| Key::Alt(CharOrArrow::Char(c)));
+    }
+    if let Some(c) = single_char(binding) {
+        return Some(Key::Char(c));
+    }
+    let key = match binding.to_lowercase().as_str() {
+        "esc" | "escape" => Key::Esc,
+        "enter" | "return" => Key::Char('\n'),
+        "tab" => Key::Char('\t'),
+        "backtab" | "shift+tab" => Key::BackTab,
+        "space" => Key::Char(' '),
+        "backspace" => Key::Backspace,
+        "delete" | "del" => Key::Delete,
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Format a key the way `parse_key` reads it, e.g. `ctrl+c` or `enter`.
+pub fn format_key(key: &Key) -> String {
+    match key {
+        Key::Char('\n') => "enter".to_string(),
+        Key::Char('\t') => "tab".to_string(),
+        Key::BackTab => "backtab".to_string(),
+        Key::Char(' ') => "space".to_string(),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("ctrl+{}", c),
+        Key::Alt(CharOrArrow::Char(c)) => format!("alt+{}", c),
+        Key::Alt(CharOrArrow::Direction(direction)) => format!(
+            "alt+{}",
+            match direction {
+                Direction::Left => "left",
+                Direction::Right => "right",
+                Direction::Up => "up",
+                Direction::Down => "down",
+            }
+        ),
+        Key::Esc => "esc".to_string(),
+        Key::Backspace => "backspace".to_string(),
+        Key::Delete => "delete".to_string(),
+        Key::Up => "up".to_string(),
+        Key::Down => "down".to_string(),
+        Key::Left => "left".to_string(),
+        Key::Right => "right".to_string(),
+        Key::Home => "home".to_string(),
+        Key::End => "end".to_string(),
+        Key::PageUp => "pageup".to_string(),
+        Key::PageDown => "pagedown".to_string(),
+        key => format!("{:?}", key),
+    }
+}
+
+fn strip_modifier<'a>(binding: &'a str, modifier: &str) -> Option<&'a str> {
+    let (prefix, rest) = binding.split_once(['+', '-'])?;
+    if prefix.eq_ignore_ascii_case(modifier) && !rest.is_empty() {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+fn single_char(value: &str) -> Option<char> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+fn parse_direction(value: &str) -> Option<Direction> {
+    match value.to_lowercase().as_str() {
+        "left" => Some(Direction::Left),
+        "right" => Some(Direction::Right),
+        "up" => Some(Direction::Up),
+        "down" => Some(Direction::Down),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(options: &[(&str, &str)]) -> (Keymap, Vec<String>) {
+        let configuration = options
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let mut errors = Vec::new();
+        (
+            Keymap::from_configuration(&configuration, &mut errors),
+            errors,
+        )
+    }
+
+    #[test]
+    fn parses_modifiers_and_names() {
+        assert_eq!(parse_key(" d "), Some(Key::Char('d')));
+        assert_eq!(parse_key("Ctrl+d"), Some(Key::Ctrl('d')));
+        assert_eq!(parse_key("alt-j"), Some(Key::Alt(CharOrArrow::Char('j'))));
+        assert_eq!(
+            parse_key("alt+Up"),
+            Some(Key::Alt(CharOrArrow::Direction(Direction::Up)))
+        );
+        assert_eq!(parse_key("ESC"), Some(Key::Esc));
+        assert_eq!(parse_key("shift+tab"), Some(Key::BackTab));
+        assert_eq!(parse_key("ctrl+"), None);
+        assert_eq!(parse_key("dd"), None);
+    }
+
+    #[test]
+    fn formatted_default_keys_parse_back() {
+        for (_, _, keys) in Action::all() {
+            for key in keys {
+                assert_eq!(parse_key(&format_key(&key)), Some(key));
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_option_keeps_defaults() {
+        let (keymap, errors) = keymap(&[("delete_key", "x,ctrl+")]);
+        assert_eq!(keymap.action(&Key::Char('d')), Some(Action::Delete));
+        assert_eq!(keymap.action(&Key::Char('x')), None);
+        assert_eq!(errors, ["Invalid delete_key: 'ctrl+'"]);
+    }
+
+    #[test]
+    fn conflicting_option_falls_back_to_defaults() {
+        let (keymap, errors) = keymap(&[("add_key", "d")]);
+        assert_eq!(keymap.action(&Key::Char('d')), Some(Action::Delete));
+        assert_eq!(keymap.action(&Key::Char('a')), Some(Action::Add));
+        assert_eq!(errors, ["add_key 'd' is also bound by delete_key"]);
+    }
+
+    #[test]
+    fn swapped_keys_are_accepted() {
+        let (keymap, errors) = keymap(&[("add_key", "d"), ("delete_key", "a")]);
+        assert_eq!(keymap.action(&Key::Char('d')), Some(Action::Add));
+        assert_eq!(keymap.action(&Key::Char('a')), Some(Action::Delete));
+        assert!(errors.is_empty());
+    }
+}
@@ -0,0 +1,60 @@
+use crate::Pane;
+
+/// A minimal zellij layout with a tab per tab name of `panes`, in the order they are first
+/// listed, holding their panes by title. Panes started with a command run it again. Pane cwds
+/// aren't reported to plugins, so panes start in the directory zellij is started from.
+pub fn layout_kdl(panes: &[Pane]) -> String {
+    let mut tabs: Vec<(&str, Vec<&Pane>)> = Vec::new();
+    for pane in panes {
+        match tabs
+            .iter_mut()
+            .find(|(name, _)| *name == pane.tab_info.name)
+        {
+            Some((_, tab_panes)) => tab_panes.push(pane),
+            None => tabs.push((&pane.tab_info.name, vec![pane])),
+        }
+    }
+    let mut kdl = String::from("layout {\n");
+    for (name, tab_panes) in tabs {
+        kdl.push_str(&format!("    tab name={} {{\n", quote(name)));
+        for pane in tab_panes {
+            kdl.push_str(&pane_node(pane));
+        }
+        kdl.push_str("    }\n");
+    }
+    kdl.push_str("}\n");
+    kdl
+}
+
+/// The `pane` node of a bookmark, with its command and arguments if it was started with one.
+fn pane_node(pane: &Pane) -> String {
+    let name = quote(pane.title());
+    let mut command = pane
+        .pane_info
+        .terminal_command
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace();
+    let Some(program) = command.next() else {
+        return format!("        pane name={}\n", name);
+    };
+    let args: Vec<String> = command.map(quote).collect();
+    if args.is_empty() {
+        return format!("        pane name={} command={}\n", name, quote(program));
+    }
+    format!(
+        "        pane name={} command={} {{\n            args {}\n        }}\n",
+        name,
+        quote(program),
+        args.join(" ")
+    )
+}
+
+/// `value` as a KDL string.
+fn quote(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
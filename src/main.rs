@@ -1,20 +1,117 @@
 use core::fmt;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
 
 use owo_colors::OwoColorize;
 use zellij_tile::prelude::*;
 
+/// Print to the zellij log when the `debug` option is set. Never use this in `render`, whose
+/// output is drawn in the pane.
+macro_rules! debug_log {
+    ($enabled:expr, $($arg:tt)*) => {
+        if $enabled {
+            eprintln!("harpoon: {}", format_args!($($arg)*));
+        }
+    };
+}
+
+mod compat;
+mod fuzzy;
+mod icons;
+mod keybindings;
+mod layout_export;
+mod persistence;
+use fuzzy::fuzzy_match;
+use icons::Icons;
+use keybindings::{format_key, parse_key, Action, Keymap};
+use persistence::{PaneBookmark, PersistFormat, Persistence, ViewPreferences};
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Pane {
     pub pane_info: PaneInfo,
     pub tab_info: TabInfo,
+    /// Pinned panes are kept at the top of the list and survive their pane being closed.
+    #[serde(default)]
+    pub pinned: bool,
+    /// Set when a pinned pane is no longer present in the session; it is kept as a placeholder.
+    #[serde(default)]
+    pub closed: bool,
+    /// Category taken from the title when the pane was added, e.g. `#work`, see `parse_tag`.
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// Comes from the shared base list, so it is shown apart and can't be removed.
+    #[serde(default)]
+    pub base: bool,
+    /// Kept for this session only, never written to disk.
+    #[serde(default)]
+    pub ephemeral: bool,
+    /// Free text attached by the user, shown above the list while the pane is selected.
+    #[serde(default)]
+    pub note: Option<String>,
+    /// Tells apart copies of a bookmark made with the duplicate action, 0 for the original.
+    #[serde(default)]
+    pub copy: u32,
+}
+
+impl Pane {
+    pub fn new(pane_info: PaneInfo, tab_info: TabInfo) -> Self {
+        Pane {
+            pane_info,
+            tab_info,
+            pinned: false,
+            closed: false,
+            tag: None,
+            base: false,
+            ephemeral: false,
+            note: None,
+            copy: 0,
+        }
+    }
+
+    /// What identifies a bookmark in the list: its pane, and which copy of it it is.
+    pub fn key(&self) -> (u32, u32) {
+        (self.pane_info.id, self.copy)
+    }
 }
 
 impl fmt::Display for Pane {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} | {}", self.tab_info.name, self.pane_info.title)
+        write!(f, "{} | {}", self.tab_info.name, self.title())
+    }
+}
+
+impl Pane {
+    /// The pane title without its tag.
+    pub fn title(&self) -> &str {
+        let title = &self.pane_info.title;
+        let Some(tag) = &self.tag else {
+            return title;
+        };
+        match title.strip_prefix(tag.as_str()) {
+            Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => {
+                rest.trim_start()
+            }
+            _ => title,
+        }
+    }
+}
+
+/// The tag a title starts with: `prefix` followed by a word, e.g. `#work` of `#work nvim`.
+fn parse_tag(title: &str, prefix: &str) -> Option<String> {
+    if prefix.is_empty() {
+        return None;
+    }
+    let word: String = title
+        .strip_prefix(prefix)?
+        .chars()
+        .take_while(|c| !c.is_whitespace())
+        .collect();
+    if word.is_empty() {
+        return None;
     }
+    Some(format!("{}{}", prefix, word))
 }
 
 //<--------- TODO: Replace with official functions once available
@@ -44,210 +141,4639 @@ fn get_focused_pane(tab_position: usize, pane_manifest: &PaneManifest) -> Option
 
 // ----------------------------------- Update ------------------------------------------------
 
+/// Where each terminal pane of a manifest is, by pane id: the position of its tab and its index
+/// among the panes of that tab.
+type PaneIndex = HashMap<u32, (usize, usize)>;
+
+fn index_panes(pane_manifest: &PaneManifest) -> PaneIndex {
+    let mut index = PaneIndex::new();
+    for (&position, panes) in &pane_manifest.panes {
+        for (idx, pane) in panes.iter().enumerate() {
+            if !pane.is_plugin {
+                index.insert(pane.id, (position, idx));
+            }
+        }
+    }
+    index
+}
+
+/// Find the terminal pane `pane_id` and the position of its tab, in any tab so panes are found
+/// after tabs were reordered. `pane_index` must have been built from `pane_manifest`.
+fn find_pane<'a>(
+    pane_id: u32,
+    pane_index: &PaneIndex,
+    pane_manifest: &'a PaneManifest,
+) -> Option<(usize, &'a PaneInfo)> {
+    let &(position, idx) = pane_index.get(&pane_id)?;
+    let pane_info = pane_manifest.panes.get(&position)?.get(idx)?;
+    Some((position, pane_info))
+}
+
 fn get_valid_panes(
     panes: &Vec<Pane>,
     pane_manifest: &PaneManifest,
+    pane_index: &PaneIndex,
     tab_infos: &Vec<TabInfo>,
+    keep_closed: bool,
 ) -> Vec<Pane> {
     let mut new_panes: Vec<Pane> = Vec::default();
     for pane in panes.clone() {
         // Iterate over all panes, and find corresponding tab and pane based on id
-        // update it in case the info has changed, and if they are not there do not add them.
-        if let Some(tab_info) = tab_infos.get(pane.tab_info.position) {
-            if let Some(other_panes) = pane_manifest.panes.get(&pane.tab_info.position) {
-                if let Some(pane_info) = other_panes
-                    .iter()
-                    .find(|p| !p.is_plugin & (p.id == pane.pane_info.id))
-                {
-                    let pane_info = pane_info.clone();
-                    let tab_info = tab_info.clone();
-                    let new_pane = Pane {
-                        pane_info,
-                        tab_info,
-                    };
-                    new_panes.push(new_pane);
-                }
-            }
+        // update it in case the info has changed, and if they are not there do not add them
+        // (unless they are pinned or `keep_closed` is set, in which case they are kept as a
+        // closed placeholder).
+        let found = find_pane(pane.pane_info.id, pane_index, pane_manifest).and_then(
+            |(position, pane_info)| {
+                let tab_info = tab_infos.iter().find(|t| t.position == position)?;
+                Some((pane_info.clone(), tab_info.clone()))
+            },
+        );
+        match found {
+            Some((pane_info, tab_info)) => new_panes.push(Pane {
+                pane_info,
+                tab_info,
+                closed: false,
+                ..pane
+            }),
+            None if pane.pinned || keep_closed => new_panes.push(Pane {
+                closed: true,
+                ..pane
+            }),
+            None => {}
         }
     }
     new_panes
 }
 
-#[derive(Default)]
-struct State {
-    selected: usize,
-    panes: Vec<Pane>,
-    focused_pane: Option<Pane>,
-    tab_info: Option<Vec<TabInfo>>,
-    pane_manifest: Option<PaneManifest>,
+/// Point closed placeholders at a pane with the same tab name and title once one shows up, e.g.
+/// after the pane was reopened. Panes that are already bookmarked are skipped, unless by another
+/// copy of the bookmark.
+fn rematch_closed_panes(panes: &mut [Pane], pane_manifest: &PaneManifest, tab_infos: &[TabInfo]) {
+    let mut used: HashSet<(u32, u32)> = panes.iter().filter(|p| !p.closed).map(Pane::key).collect();
+    for pane in panes.iter_mut().filter(|p| p.closed) {
+        let Some(tab_info) = tab_infos.iter().find(|t| t.name == pane.tab_info.name) else {
+            continue;
+        };
+        let Some(tab_panes) = pane_manifest.panes.get(&tab_info.position) else {
+            continue;
+        };
+        if let Some(pane_info) = tab_panes.iter().find(|p| {
+            !p.is_plugin && p.title == pane.pane_info.title && !used.contains(&(p.id, pane.copy))
+        }) {
+            used.insert((pane_info.id, pane.copy));
+            pane.pane_info = pane_info.clone();
+            pane.tab_info = tab_info.clone();
+            pane.closed = false;
+        }
+    }
 }
 
-impl State {
-    fn select_down(&mut self) {
-        self.selected = (self.selected + 1) % self.panes.len();
+/// Update pane infos from the manifest alone, keeping the last known tab of each pane. Panes
+/// that are gone are dropped, unless pinned or `keep_closed` is set.
+fn refresh_pane_infos(
+    panes: &mut Vec<Pane>,
+    pane_manifest: &PaneManifest,
+    pane_index: &PaneIndex,
+    keep_closed: bool,
+) {
+    panes.retain_mut(
+        |pane| match find_pane(pane.pane_info.id, pane_index, pane_manifest) {
+            Some((position, pane_info)) => {
+                pane.pane_info = pane_info.clone();
+                pane.tab_info.position = position;
+                pane.closed = false;
+                true
+            }
+            None => {
+                pane.closed = true;
+                pane.pinned || keep_closed
+            }
+        },
+    );
+}
+
+/// Update the tab info of panes from the tabs alone, e.g. to pick up renamed tabs.
+fn refresh_tab_infos(panes: &mut [Pane], tab_infos: &[TabInfo]) {
+    for pane in panes {
+        if let Some(tab_info) = tab_infos
+            .iter()
+            .find(|t| t.position == pane.tab_info.position)
+        {
+            pane.tab_info = tab_info.clone();
+        }
     }
+}
 
-    fn select_up(&mut self) {
-        if self.selected == 0 {
-            self.selected = self.panes.len() - 1;
-            return;
+/// All terminal panes of the session, ordered by tab position.
+fn get_all_panes(pane_manifest: &PaneManifest, tab_infos: &[TabInfo]) -> Vec<Pane> {
+    let mut panes = Vec::new();
+    for tab_info in tab_infos {
+        if let Some(tab_panes) = pane_manifest.panes.get(&tab_info.position) {
+            for pane_info in tab_panes.iter().filter(|p| !p.is_plugin) {
+                panes.push(Pane::new(pane_info.clone(), tab_info.clone()));
+            }
         }
-        self.selected = self.selected - 1;
     }
+    panes
+}
 
-    fn sort_panes(&mut self) {
-        self.panes.sort_by(|x, y| {
-            (x.tab_info.position)
-                .partial_cmp(&y.tab_info.position)
-                .unwrap()
-        });
+/// Position of `pane_info` among the terminal panes of its tab with the same title, if there are
+/// several of them.
+fn same_title_index(pane_info: &PaneInfo, tab_panes: &[PaneInfo]) -> Option<usize> {
+    let same_title: Vec<u32> = tab_panes
+        .iter()
+        .filter(|p| !p.is_plugin && p.title == pane_info.title)
+        .map(|p| p.id)
+        .collect();
+    if same_title.len() < 2 {
+        return None;
     }
+    same_title.iter().position(|&id| id == pane_info.id)
+}
 
-    /// Update panes updates the pane states based on the latest pane_manifest and tab_info
-    fn update_panes(&mut self) -> Option<()> {
-        // Update panes to filter our invalid panes (e.g. tab/pane was closed).
-        let pane_manifest = self.pane_manifest.clone()?;
-        let tab_info = self.tab_info.clone()?;
-        let panes = get_valid_panes(&self.panes.clone(), &pane_manifest, &tab_info);
-        self.panes = panes;
+/// Move `selected` by `delta` within a list of `len` items, wrapping around the ends or clamping
+/// to them. On an empty list the selection is 0.
+fn step_selection(selected: usize, delta: isize, len: usize, wrap: bool) -> usize {
+    let len = len as isize;
+    if len == 0 {
+        return 0;
+    }
+    let target = selected as isize + delta;
+    if wrap {
+        target.rem_euclid(len) as usize
+    } else {
+        target.clamp(0, len - 1) as usize
+    }
+}
 
-        // Update currently focused pane
-        let tab_info = get_focused_tab(&tab_info)?;
-        let pane_info = get_focused_pane(tab_info.position, &pane_manifest)?;
-        self.focused_pane = Some(Pane {
-            pane_info,
-            tab_info,
-        });
+/// Parse the 1-based index of a pipe message into an index of a list of `len` bookmarks.
+fn parse_pipe_index(argument: &str, len: usize) -> Option<usize> {
+    let index: usize = argument.trim().parse().ok()?;
+    (1..=len).contains(&index).then(|| index - 1)
+}
 
-        // Set default location of selected idx to currently focused pane
-        if let Some(focused_pane) = &self.focused_pane {
-            for (idx,pane) in self.panes.iter().enumerate() {
-                if pane.pane_info.id == focused_pane.pane_info.id {
-                    self.selected = idx;
-                }
-            }
-        }else{
-            self.selected = 0;
+/// Whether `title` matches `pattern`: a glob where `*` matches any run of characters, or else
+/// a substring.
+fn matches_pattern(title: &str, pattern: &str) -> bool {
+    if !pattern.contains('*') {
+        return title.contains(pattern);
+    }
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, rest) = parts.split_first().unwrap_or((&"", &[]));
+    let Some(mut remaining) = title.strip_prefix(first) else {
+        return false;
+    };
+    let (last, middle) = rest.split_last().unwrap_or((&"", &[]));
+    for part in middle {
+        match remaining.find(part) {
+            Some(idx) => remaining = &remaining[idx + part.len()..],
+            None => return false,
         }
+    }
+    remaining.ends_with(last)
+}
 
-        Some(())
+/// Whether the command of a command pane, e.g. started with `zellij run`, is running (`●`),
+/// exited successfully (`✓`) or failed (`✗`). Other panes have no status.
+fn status_glyph(pane_info: &PaneInfo) -> Option<&'static str> {
+    pane_info.terminal_command.as_ref()?;
+    if !pane_info.exited && !pane_info.is_held {
+        return Some("●");
+    }
+    match pane_info.exit_status {
+        Some(0) => Some("✓"),
+        _ => Some("✗"),
     }
 }
 
-register_plugin!(State);
+/// The text the copy action copies from `pane` and what it is: the command of a command pane,
+/// else its title.
+fn clipboard_text(pane: &Pane) -> (&str, &'static str) {
+    match &pane.pane_info.terminal_command {
+        Some(command) => (command, "command"),
+        None => (&pane.pane_info.title, "title"),
+    }
+}
 
-impl ZellijPlugin for State {
-    fn load(&mut self, _: BTreeMap<String, String>) {
-        request_permission(&[
-            PermissionType::RunCommands,
-            PermissionType::ReadApplicationState,
-            PermissionType::ChangeApplicationState,
-        ]);
-        subscribe(&[EventType::Key, EventType::TabUpdate, EventType::PaneUpdate]);
+/// Whether the pane is a collapsed member of a stack, which zellij squeezes to its title row.
+/// zellij-tile doesn't tell stacks apart otherwise.
+fn is_collapsed_in_stack(pane_info: &PaneInfo) -> bool {
+    !pane_info.is_floating && pane_info.pane_rows == 1
+}
+
+/// `text` cut to `max` characters, ending in an ellipsis when it was cut.
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
     }
+    let mut text: String = text.chars().take(max.saturating_sub(1)).collect();
+    text.push('…');
+    text
+}
 
-    fn update(&mut self, event: Event) -> bool {
-        let mut should_render = false;
-        match event {
-            Event::TabUpdate(tab_info) => {
-                self.tab_info = Some(tab_info);
-                self.update_panes();
-                should_render = true;
-            }
-            Event::PaneUpdate(pane_manifest) => {
-                self.pane_manifest = Some(pane_manifest);
-                self.update_panes();
-                should_render = true;
-            }
-            Event::Key(Key::Char('A')) => {
-                let current_pane_ids: Vec<u32> = self.panes.iter().map(|p| p.pane_info.id).collect();
-                if let Some(pane_manifest) = &self.pane_manifest {
-                    if let Some(tab_info) = &self.tab_info {
-                        for (tab_position, panes) in &pane_manifest.panes {
-                            if let Some(tab) = tab_info.iter().find(|t| t.position == *tab_position) {
-                                for pane in panes {
-                                    if !pane.is_plugin && !current_pane_ids.contains(&pane.id) {
-                                        self.panes.push(Pane {
-                                            pane_info: pane.clone(),
-                                            tab_info: tab.clone(),
-                                        });
-                                    }
-                                }
-                            }
-                        }
-                    }
+/// The first row shown of a list of `len` rows in `height` rows: `offset` moved just enough to
+/// keep `selected` in view, and never past the end of the list.
+fn scroll_offset(offset: usize, selected: usize, len: usize, height: usize) -> usize {
+    if len <= height {
+        return 0;
+    }
+    let offset = if selected < offset {
+        selected
+    } else if selected >= offset + height {
+        selected + 1 - height
+    } else {
+        offset
+    };
+    offset.min(len - height)
+}
+
+// ----------------------------------- Config ------------------------------------------------
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SortMode {
+    /// Bookmarks are ordered by the position of their tab.
+    #[default]
+    Tab,
+    /// Bookmarks keep the order they were added in, and can be reordered by hand.
+    Manual,
+    /// Bookmarks are ordered by their label, ignoring case.
+    Alpha,
+    /// The most recently focused bookmarks come first, those not focused since the plugin
+    /// started last.
+    Recent,
+}
+
+impl SortMode {
+    fn from_str(value: &str) -> Option<Self> {
+        match value.trim() {
+            "tab" => Some(SortMode::Tab),
+            "manual" => Some(SortMode::Manual),
+            "alpha" => Some(SortMode::Alpha),
+            "recent" => Some(SortMode::Recent),
+            _ => None,
+        }
+    }
+
+    /// The mode after this one, cycling through all of them.
+    fn next(&self) -> Self {
+        match self {
+            SortMode::Tab => SortMode::Recent,
+            SortMode::Recent => SortMode::Alpha,
+            SortMode::Alpha => SortMode::Manual,
+            SortMode::Manual => SortMode::Tab,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SortMode::Tab => "tab",
+            SortMode::Manual => "manual",
+            SortMode::Alpha => "alpha",
+            SortMode::Recent => "recent",
+        }
+    }
+}
+
+/// Where the selection starts when the plugin is shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum InitialSelection {
+    /// The bookmark of the focused pane, keeping the selection when it isn't bookmarked.
+    #[default]
+    Focused,
+    Top,
+    /// The middle of the list, rounding down for even lengths.
+    Middle,
+}
+
+impl InitialSelection {
+    fn from_str(value: &str) -> Option<Self> {
+        match value.trim() {
+            "focused" => Some(InitialSelection::Focused),
+            "top" => Some(InitialSelection::Top),
+            "middle" => Some(InitialSelection::Middle),
+            _ => None,
+        }
+    }
+
+    /// The index to select in a list of `len` bookmarks, if it doesn't depend on the focus.
+    fn index(&self, len: usize) -> Option<usize> {
+        match self {
+            InitialSelection::Focused => None,
+            InitialSelection::Top => Some(0),
+            // `len - 1` keeps the index in the list, e.g. 0 for 2 bookmarks and 1 for 4.
+            InitialSelection::Middle => Some(len.saturating_sub(1) / 2),
+        }
+    }
+}
+
+/// How the list is laid out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum Layout {
+    /// One bookmark per row.
+    #[default]
+    List,
+    /// All bookmarks on a single row, for a status bar sized pane.
+    Bar,
+}
+
+impl Layout {
+    fn from_str(value: &str) -> Option<Self> {
+        match value.trim() {
+            "list" => Some(Layout::List),
+            "bar" => Some(Layout::Bar),
+            _ => None,
+        }
+    }
+}
+
+/// Pipe message selecting the bookmark at the 1-based index given as payload (or `index`
+/// argument), without focusing it.
+const SELECT_PIPE: &str = "harpoon::select";
+/// Pipe message merging the bookmarks of its JSON payload, in the format of the bookmark file,
+/// into the list.
+const IMPORT_PIPE: &str = "harpoon::import";
+/// Pipe message switching to the first open bookmark tagged with the payload, with or without
+/// the tag prefix. Bookmarks have no aliases of their own, so their tag serves as one.
+const FOCUS_ALIAS_PIPE: &str = "harpoon::focus_alias";
+/// Pipe message switching to the first open bookmark whose label, e.g. `Tab #1 | nvim`, matches
+/// the payload, see `matches_pattern`.
+const FOCUS_MATCH_PIPE: &str = "harpoon::focus_match";
+/// Pipe message writing the bookmarks as a zellij layout to the path given as payload.
+const EXPORT_LAYOUT_PIPE: &str = "harpoon::export_layout";
+/// Pipe message saving a snapshot of the list as the preset named by the payload.
+const SAVE_PRESET_PIPE: &str = "harpoon::save_preset";
+/// Pipe message replacing the list by the preset named by the payload, after confirmation.
+const LOAD_PRESET_PIPE: &str = "harpoon::load_preset";
+/// Pipe message showing the plugin straight into a search, whose best match `Enter` switches to.
+const SWITCHER_PIPE: &str = "harpoon::switcher";
+/// Pipe message reverting the settings changed at runtime to their configured values.
+const RESET_CONFIG_PIPE: &str = "harpoon::reset_config";
+
+/// How long `preview_on_navigate` marks the frame of a pane.
+const PREVIEW_SECS: f64 = 0.6;
+/// Shortest time between two marks of `preview_on_navigate`.
+const PREVIEW_THROTTLE: Duration = Duration::from_millis(300);
+/// Name shown in the frame of the pane marked by `preview_on_navigate`.
+const PREVIEW_NAME: &str = "◆ harpoon ◆";
+
+/// A pane marked by `preview_on_navigate`, with the title to keep showing for it in the list.
+struct Preview {
+    pane_id: u32,
+    title: String,
+    at: Instant,
+}
+
+/// How the selected row stands out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum SelectionStyle {
+    /// Red bold text.
+    #[default]
+    Fg,
+    /// A grey background, which reads well on light terminals too.
+    Bg,
+    /// Swapped foreground and background colors.
+    Invert,
+    /// A colored bar in a gutter column before the row, leaving the text as is.
+    Gutter,
+}
+
+impl SelectionStyle {
+    fn from_str(value: &str) -> Option<Self> {
+        match value.trim() {
+            "fg" => Some(SelectionStyle::Fg),
+            "bg" => Some(SelectionStyle::Bg),
+            "invert" => Some(SelectionStyle::Invert),
+            "gutter" => Some(SelectionStyle::Gutter),
+            _ => None,
+        }
+    }
+}
+
+/// How titles holding a path, as shells set them to the working directory, are shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum CwdDisplay {
+    /// The whole path, with a leading `~` expanded when the home directory is known.
+    Absolute,
+    /// The path with the home directory shortened to `~`.
+    #[default]
+    Relative,
+    /// Only the last component of the path.
+    Basename,
+}
+
+impl CwdDisplay {
+    fn from_str(value: &str) -> Option<Self> {
+        match value.trim() {
+            "absolute" => Some(CwdDisplay::Absolute),
+            "relative" => Some(CwdDisplay::Relative),
+            "basename" => Some(CwdDisplay::Basename),
+            _ => None,
+        }
+    }
+
+    /// `path` as shown in this mode. Plugins can't read `$HOME`, so `home` comes from the
+    /// `home_dir` option; without it paths are left as they are, apart from `basename`.
+    fn format(self, path: &str, home: Option<&str>) -> String {
+        let home = home
+            .map(|h| h.trim_end_matches('/'))
+            .filter(|h| !h.is_empty());
+        match self {
+            CwdDisplay::Absolute => match (home, path.strip_prefix('~')) {
+                (Some(home), Some(rest)) if rest.is_empty() || rest.starts_with('/') => {
+                    format!("{}{}", home, rest)
                 }
-                self.sort_panes();
-                should_render = true;
-                hide_self();
+                _ => path.to_string(),
             },
-            Event::Key(Key::Char('a')) => {
-                let panes_ids: Vec<u32> = self.panes.iter().map(|p| p.pane_info.id).collect();
-                if let Some(pane) = &self.focused_pane {
-                    if !panes_ids.contains(&pane.pane_info.id) {
-                        self.panes.push(pane.clone());
-                        self.sort_panes();
-                    }
-                }
-                should_render = true;
-                hide_self();
-            }
-            Event::Key(Key::Char('d')) => {
-                if self.selected < self.panes.len() {
-                    self.panes.remove(self.selected);
-                }
-                if self.panes.len() > 0 {
-                    self.select_up();
+            CwdDisplay::Relative => match home.and_then(|home| path.strip_prefix(home)) {
+                Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("~{}", rest),
+                _ => path.to_string(),
+            },
+            CwdDisplay::Basename => {
+                let trimmed = path.trim_end_matches('/');
+                match trimmed.rsplit_once('/') {
+                    Some((_, name)) if !name.is_empty() => name.to_string(),
+                    // `/` itself, or a bare `~`.
+                    _ if trimmed.is_empty() => "/".to_string(),
+                    _ => trimmed.to_string(),
                 }
-                should_render = true;
             }
+        }
+    }
+}
+
+/// Whether a title is a path, e.g. the working directory a shell put in it.
+fn is_path(title: &str) -> bool {
+    title.starts_with('/') || title == "~" || title.starts_with("~/")
+}
+
+/// How the list marks where the bookmarks of one tab end and those of the next begin.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum TabSeparator {
+    /// Nothing apart from the tab labels.
+    #[default]
+    None,
+    /// A row naming the next tab, taking a row of the list.
+    Row,
+    /// An underline on the last bookmark of a tab, taking no row. Not drawn without colors.
+    Inline,
+}
+
+impl TabSeparator {
+    fn from_str(value: &str) -> Option<Self> {
+        match value.trim() {
+            "none" => Some(TabSeparator::None),
+            "row" => Some(TabSeparator::Row),
+            "inline" => Some(TabSeparator::Inline),
+            _ => None,
+        }
+    }
+}
+
+/// What happens to a bookmark when its pane is closed. Pinned bookmarks are always kept.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum OnPaneClose {
+    /// The bookmark is removed.
+    #[default]
+    Remove,
+    /// The bookmark is kept as a closed placeholder, until a pane with the same tab and title
+    /// shows up.
+    Keep,
+}
+
+impl OnPaneClose {
+    fn from_str(value: &str) -> Option<Self> {
+        match value.trim() {
+            "remove" => Some(OnPaneClose::Remove),
+            "keep" => Some(OnPaneClose::Keep),
+            _ => None,
+        }
+    }
+}
+
+/// Separates the bookmarks of the `bar` layout.
+const BAR_DELIMITER: &str = "  ";
+
+struct Config {
+    /// Whether moving past either end of the list wraps around (true) or stops at the end (false).
+    wrap_navigation: bool,
+    /// Whether bookmarks are saved to disk. Saving runs shell commands, so without it the
+    /// `RunCommands` permission isn't requested.
+    persistence: bool,
+    /// File format bookmarks are saved in.
+    persist_format: PersistFormat,
+    /// Whether the plugin hides itself after adding panes.
+    hide_after_add: bool,
+    /// Mark the frame of the pane under the cursor while navigating, see `preview_selected`.
+    preview_on_navigate: bool,
+    keymap: Keymap,
+    /// Log lifecycle events to the zellij log, to troubleshoot persistence.
+    debug: bool,
+    sort_mode: SortMode,
+    /// Whether output is colored. Without colors the selection is marked by `selection_marker`.
+    color: bool,
+    selection_marker: String,
+    /// Only show the tab name on the first of consecutive bookmarks in the same tab.
+    collapse_tab_labels: bool,
+    tab_separator: TabSeparator,
+    /// Drop bookmarks of a pane that is already bookmarked higher up the list.
+    dedup_by_pane_id: bool,
+    /// Start with the list locked against changes.
+    locked: bool,
+    initial_selection: InitialSelection,
+    /// Save where bookmarked panes are on screen.
+    record_geometry: bool,
+    layout: Layout,
+    /// Titles starting with this prefix and a word are tagged with it, e.g. `#work`. Empty
+    /// disables tags.
+    tag_prefix: String,
+    /// Patterns of titles skipped when adding all panes, see `matches_pattern`.
+    ignore_titles: Vec<String>,
+    /// Shared bookmark file merged underneath the session's bookmarks.
+    base_list_path: Option<String>,
+    /// Hide the plugin as soon as it is loaded, until it is summoned.
+    start_hidden: bool,
+    /// Show Nerd Font icons before bookmarks, by the command running in the pane.
+    icons: bool,
+    icon_map: Icons,
+    /// Show whether the command of command panes is running or exited.
+    show_status: bool,
+    /// Key that, followed by a digit, jumps to that bookmark. `None` disables jumps.
+    jump_leader: Option<Key>,
+    selection_style: SelectionStyle,
+    cwd_display: CwdDisplay,
+    /// The home directory, which `cwd_display` shortens to `~`.
+    home_dir: Option<String>,
+    /// Hide the plugin when no key was pressed for this long.
+    auto_hide: Option<Duration>,
+    /// Write unsaved changes this often, in case the debounced save didn't get to.
+    save_interval: Option<Duration>,
+    on_pane_close: OnPaneClose,
+    /// Cap on the length of pane titles, on top of the width of the plugin.
+    max_title_len: Option<usize>,
+    /// Cap on the number of rows of the list, on top of the height of the plugin.
+    max_rows: Option<usize>,
+    /// Show the id of each pane after its title, to help troubleshooting.
+    show_ids: bool,
+    /// Show the active modes below the list.
+    show_footer: bool,
+    /// Write removals of bookmarks to disk, rather than only removing them for the session.
+    delete_persists: bool,
+    /// Drop pending bookmarks unmatched after this many pane updates.
+    prune_pending_after: Option<usize>,
+    /// Show the list from its end, e.g. the pane added last first.
+    reverse: bool,
+    /// Zoom panes when switching to them.
+    zoom_on_focus: bool,
+    /// Passed to `focus_terminal_pane` as `should_float_if_hidden`: when focusing a floating pane
+    /// while floating panes are hidden, show them (true) or leave them hidden (false).
+    focus_float_if_hidden: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            wrap_navigation: true,
+            persistence: true,
+            persist_format: PersistFormat::Json,
+            hide_after_add: true,
+            preview_on_navigate: false,
+            keymap: Keymap::default(),
+            debug: false,
+            sort_mode: SortMode::Tab,
+            color: true,
+            selection_marker: "> ".to_string(),
+            collapse_tab_labels: false,
+            tab_separator: TabSeparator::None,
+            dedup_by_pane_id: false,
+            locked: false,
+            initial_selection: InitialSelection::Focused,
+            record_geometry: false,
+            layout: Layout::List,
+            tag_prefix: "#".to_string(),
+            ignore_titles: Vec::new(),
+            base_list_path: None,
+            start_hidden: false,
+            icons: false,
+            show_status: false,
+            icon_map: Icons::default(),
+            jump_leader: Some(Key::Char(' ')),
+            selection_style: SelectionStyle::Fg,
+            cwd_display: CwdDisplay::Relative,
+            home_dir: None,
+            auto_hide: None,
+            save_interval: None,
+            on_pane_close: OnPaneClose::Remove,
+            max_title_len: None,
+            max_rows: None,
+            show_ids: false,
+            show_footer: false,
+            delete_persists: true,
+            prune_pending_after: None,
+            reverse: false,
+            zoom_on_focus: false,
+            focus_float_if_hidden: true,
+        }
+    }
+}
+
+impl Config {
+    /// Parse the plugin configuration, falling back to defaults for missing options. Problems
+    /// with invalid options are reported in `errors`.
+    fn from_configuration(
+        configuration: &BTreeMap<String, String>,
+        errors: &mut Vec<String>,
+    ) -> Self {
+        let default = Config::default();
+        Config {
+            wrap_navigation: parse_bool(configuration, "wrap_navigation", default.wrap_navigation),
+            persistence: parse_bool(configuration, "persistence", default.persistence),
+            persist_format: configuration
+                .get("persist_format")
+                .and_then(|v| PersistFormat::from_str(v))
+                .unwrap_or(default.persist_format),
+            hide_after_add: parse_bool(configuration, "hide_after_add", default.hide_after_add),
+            preview_on_navigate: parse_bool(
+                configuration,
+                "preview_on_navigate",
+                default.preview_on_navigate,
+            ),
+            keymap: Keymap::from_configuration(configuration, errors),
+            debug: parse_bool(configuration, "debug", default.debug),
+            sort_mode: configuration
+                .get("sort_mode")
+                .and_then(|v| SortMode::from_str(v))
+                .unwrap_or(default.sort_mode),
+            color: parse_color(configuration, default.color),
+            selection_marker: configuration
+                .get("selection_marker")
+                .cloned()
+                .unwrap_or(default.selection_marker),
+            collapse_tab_labels: parse_bool(
+                configuration,
+                "collapse_tab_labels",
+                default.collapse_tab_labels,
+            ),
+            tab_separator: configuration
+                .get("tab_separator")
+                .and_then(|v| TabSeparator::from_str(v))
+                .unwrap_or(default.tab_separator),
+            dedup_by_pane_id: parse_bool(
+                configuration,
+                "dedup_by_pane_id",
+                default.dedup_by_pane_id,
+            ),
+            locked: parse_bool(configuration, "locked", default.locked),
+            initial_selection: configuration
+                .get("initial_selection")
+                .and_then(|v| InitialSelection::from_str(v))
+                .unwrap_or(default.initial_selection),
+            record_geometry: parse_bool(configuration, "record_geometry", default.record_geometry),
+            layout: configuration
+                .get("layout")
+                .and_then(|v| Layout::from_str(v))
+                .unwrap_or(default.layout),
+            tag_prefix: configuration
+                .get("tag_prefix")
+                .cloned()
+                .unwrap_or(default.tag_prefix),
+            ignore_titles: configuration
+                .get("ignore_titles")
+                .map(|v| {
+                    v.split(',')
+                        .map(|pattern| pattern.trim().to_string())
+                        .filter(|pattern| !pattern.is_empty())
+                        .collect()
+                })
+                .unwrap_or(default.ignore_titles),
+            base_list_path: configuration.get("base_list_path").cloned(),
+            start_hidden: parse_bool(configuration, "start_hidden", default.start_hidden),
+            icons: parse_bool(configuration, "icons", default.icons),
+            show_status: parse_bool(configuration, "show_status", default.show_status),
+            icon_map: Icons::from_configuration(configuration, errors),
+            jump_leader: parse_jump_leader(configuration, default.jump_leader, errors),
+            selection_style: configuration
+                .get("selection_style")
+                .and_then(|v| SelectionStyle::from_str(v))
+                .unwrap_or(default.selection_style),
+            cwd_display: configuration
+                .get("cwd_display")
+                .and_then(|v| CwdDisplay::from_str(v))
+                .unwrap_or(default.cwd_display),
+            home_dir: configuration.get("home_dir").cloned(),
+            auto_hide: configuration
+                .get("auto_hide_ms")
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis),
+            save_interval: configuration
+                .get("save_interval_ms")
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .filter(|&ms| ms > 0)
+                .map(Duration::from_millis),
+            on_pane_close: configuration
+                .get("on_pane_close")
+                .and_then(|v| OnPaneClose::from_str(v))
+                .unwrap_or(default.on_pane_close),
+            max_title_len: configuration
+                .get("max_title_len")
+                .and_then(|v| v.trim().parse::<usize>().ok())
+                .filter(|&len| len > 0),
+            max_rows: configuration
+                .get("max_rows")
+                .and_then(|v| v.trim().parse::<usize>().ok())
+                .filter(|&rows| rows > 0),
+            show_ids: parse_bool(configuration, "show_ids", default.show_ids),
+            show_footer: parse_bool(configuration, "show_footer", default.show_footer),
+            delete_persists: parse_bool(configuration, "delete_persists", default.delete_persists),
+            prune_pending_after: configuration
+                .get("prune_pending_after")
+                .and_then(|v| v.trim().parse::<usize>().ok())
+                .filter(|&updates| updates > 0),
+            reverse: parse_bool(configuration, "reverse", default.reverse),
+            zoom_on_focus: parse_bool(configuration, "zoom_on_focus", default.zoom_on_focus),
+            focus_float_if_hidden: parse_bool(
+                configuration,
+                "focus_float_if_hidden",
+                default.focus_float_if_hidden,
+            ),
+        }
+    }
 
-            Event::Key(Key::Esc | Key::Ctrl('c')) => {
-                hide_self();
+    /// Whether a pane with `title` is skipped by `ignore_titles`.
+    fn is_ignored(&self, title: &str) -> bool {
+        (self.ignore_titles.iter()).any(|pattern| matches_pattern(title, pattern))
+    }
+
+    fn permissions(&self) -> Vec<PermissionType> {
+        let mut permissions = vec![
+            PermissionType::ReadApplicationState,
+            PermissionType::ChangeApplicationState,
+        ];
+        if self.persistence {
+            permissions.push(PermissionType::RunCommands);
+        }
+        permissions
+    }
+}
+
+/// `color` is `always`, `never` or `auto`. Plugins can't read the environment, so `auto` follows
+/// the [NO_COLOR](https://no-color.org) convention through a `NO_COLOR` option instead.
+fn parse_color(configuration: &BTreeMap<String, String>, default: bool) -> bool {
+    match configuration.get("color").map(|v| v.trim()) {
+        Some("always") => true,
+        Some("never") => false,
+        _ => match configuration.get("NO_COLOR") {
+            Some(no_color) if !no_color.is_empty() => false,
+            _ => default,
+        },
+    }
+}
+
+/// `jump_leader` is a key as in the key options, or `none` to disable jumps.
+fn parse_jump_leader(
+    configuration: &BTreeMap<String, String>,
+    default: Option<Key>,
+    errors: &mut Vec<String>,
+) -> Option<Key> {
+    let Some(value) = configuration.get("jump_leader").map(|v| v.trim()) else {
+        return default;
+    };
+    if value.is_empty() || value == "none" {
+        return None;
+    }
+    match parse_key(value) {
+        Some(key) => Some(key),
+        None => {
+            errors.push(format!("Invalid jump_leader: '{}'", value));
+            default
+        }
+    }
+}
+
+fn parse_bool(configuration: &BTreeMap<String, String>, key: &str, default: bool) -> bool {
+    match configuration.get(key).map(|v| v.trim()) {
+        Some("true" | "on") => true,
+        Some("false" | "off") => false,
+        _ => default,
+    }
+}
+
+// ----------------------------------- State -------------------------------------------------
+
+/// How long a newly added pane stays highlighted.
+const JUST_ADDED_HIGHLIGHT_SECS: f64 = 1.0;
+/// Upper bound of a count typed before a motion.
+const MAX_COUNT: usize = 9999;
+/// Changes are written to disk this long after the first unsaved change, to batch quick edits.
+const SAVE_DEBOUNCE_SECS: f64 = 0.5;
+/// How long a jump waits for its digit after the leader key.
+const JUMP_TIMEOUT_SECS: f64 = 1.0;
+
+/// Browsing lists every pane of the session instead of the bookmarks, to bookmark or focus any of
+/// them.
+struct Browse {
+    panes: Vec<Pane>,
+    selected: usize,
+}
+
+/// Narrows the bookmarks down to those whose label contains the query.
+#[derive(Default)]
+struct Search {
+    query: String,
+    /// Whether keys are typed into the query, or handled as usual.
+    editing: bool,
+    /// Opened by `SWITCHER_PIPE`: the best match stays selected and `Enter` switches to it.
+    switcher: bool,
+}
+
+impl Search {
+    /// Fuzzy match the label of `pane` against the query, see `fuzzy_match`.
+    /// A query starting with `tag_prefix` only matches panes with that tag, e.g. `#work nv`.
+    fn score(&self, pane: &Pane, tag_prefix: &str) -> Option<(i64, Vec<usize>)> {
+        let mut query = self.query.as_str();
+        if !tag_prefix.is_empty() && query.starts_with(tag_prefix) {
+            let (tag, rest) = query.split_once(' ').unwrap_or((query, ""));
+            if pane.tag.as_deref() != Some(tag) {
+                return None;
             }
+            query = rest.trim_start();
+        }
+        fuzzy_match(query, &pane.to_string())
+    }
+}
 
-            Event::Key(Key::Down | Key::Char('j')) => {
-                if self.panes.len() > 0 {
-                    self.select_down();
-                    should_render = true;
-                }
+#[derive(Default)]
+struct State {
+    selected: usize,
+    panes: Vec<Pane>,
+    /// The last focused terminal pane. It is kept while the focus is briefly unknown during
+    /// updates, so a quick add still works, and only dropped once the pane is closed.
+    focused_pane: Option<Pane>,
+    tab_info: Option<Vec<TabInfo>>,
+    pane_manifest: Option<PaneManifest>,
+    /// Where the panes of `pane_manifest` are, rebuilt with it.
+    pane_index: PaneIndex,
+    /// When each pane was last focused, counted in focus changes, for the `recent` sort mode.
+    last_focused: HashMap<u32, u64>,
+    focus_count: u64,
+    config: Config,
+    /// Number of rows available at the last render, used as the page size.
+    rows: usize,
+    /// Number of columns available at the last render, the width details are wrapped to.
+    cols: usize,
+    /// Feedback for the last action, shown above the list until the next key press.
+    message: Option<String>,
+    /// Last error, shown above the list until the next key press.
+    last_error: Option<String>,
+    persistence: Persistence,
+    /// Id of the pane that was just added and when its highlight ends.
+    just_added: Option<(u32, Instant)>,
+    /// The pane whose frame is marked by `preview_selected`.
+    preview: Option<Preview>,
+    /// Id of a stacked pane just focused, checked for being expanded by `expand_stacked`.
+    expanding: Option<u32>,
+    browse: Option<Browse>,
+    /// Set when the bookmarks changed since they were last written to disk.
+    dirty: bool,
+    search: Option<Search>,
+    /// Count typed before a motion, e.g. the 3 of `3j`.
+    pending_count: Option<usize>,
+    /// While locked, the list can be navigated and focused but not changed.
+    locked: bool,
+    /// Whether the key binding overview is shown instead of the list.
+    help: bool,
+    /// Whether the details of the selected bookmark are shown instead of the list.
+    details: bool,
+    /// First row of the details shown, reset whenever the selection changes.
+    detail_scroll: usize,
+    /// First row of the list shown at the last render.
+    scroll: usize,
+    /// When the jump leader was pressed, kept until a digit or the timeout.
+    awaiting_jump: Option<Instant>,
+    /// Set after the type-ahead key was pressed, until the next key.
+    awaiting_type_ahead: bool,
+    /// When the last key was pressed while shown, for `auto_hide_ms`.
+    last_activity: Option<Instant>,
+    /// When the `save_interval_ms` timer was last started.
+    last_interval_save: Option<Instant>,
+    /// Bookmarks of an import or preset replacing the list, waiting for confirmation, and the
+    /// name of the preset.
+    confirm_replace: Option<(Vec<PaneBookmark>, Option<String>)>,
+    /// The settings that can be changed at runtime, as configured. Restored by
+    /// `harpoon::reset_config`.
+    configured: ViewPreferences,
+    /// `show_ids` as configured, restored by `harpoon::reset_config` as well.
+    configured_show_ids: bool,
+    /// Number of panes added since the add mode was started, while it is on.
+    adding: Option<usize>,
+    /// Set when the plugin was hidden while handling the current event. A hidden plugin needs no
+    /// render, so in debug builds a key that hides the plugin and requests one is caught.
+    hid: bool,
+    /// Id of a focused pane matching `ignore_titles`, added if the add key is pressed again.
+    confirm_ignored: Option<u32>,
+    /// Text typed for the note of the selected bookmark, while it is being edited.
+    note_input: Option<String>,
+    /// Set while saving waits for the session name to come back.
+    persistence_paused: bool,
+    /// Keys of the bookmarks marked for a bulk removal, see `Pane::key`.
+    marked: HashSet<(u32, u32)>,
+}
+
+impl State {
+    /// Move the selection of the shown list by `delta` rows. Depending on `wrap_navigation` the
+    /// selection either wraps around the ends of the list or is clamped to them. On an empty list
+    /// the selection is reset to 0.
+    fn move_selection(&mut self, delta: isize) {
+        let wrap = self.config.wrap_navigation;
+        self.detail_scroll = 0;
+        match &mut self.browse {
+            Some(browse) => {
+                browse.selected = step_selection(browse.selected, delta, browse.panes.len(), wrap)
             }
-            Event::Key(Key::Up | Key::Char('k')) => {
-                if self.panes.len() > 0 {
-                    self.select_up();
-                    should_render = true;
-                }
+            None => {
+                // `selected` always indexes into all panes, so step through the visible ones.
+                let visible = self.visible_indices();
+                let position = visible.iter().position(|&idx| idx == self.selected);
+                let position = match position {
+                    Some(position) => step_selection(position, delta, visible.len(), wrap),
+                    None => 0,
+                };
+                self.selected = visible.get(position).copied().unwrap_or(0);
             }
-            Event::Key(Key::Char('\n') | Key::Char('l')) => {
-                let pane = self.panes.get(self.selected);
+        }
+    }
 
-                if let Some(pane) = pane {
-                    hide_self();
-                    // TODO: This has a bug on macOS with hidden panes
-                    focus_terminal_pane(pane.pane_info.id, true);
-                }
+    /// Number of entries in the shown list.
+    fn list_len(&self) -> usize {
+        match &self.browse {
+            Some(browse) => browse.panes.len(),
+            None => self.visible_indices().len(),
+        }
+    }
+
+    /// Indices of the bookmarks shown, i.e. those matching the search filter if there is one.
+    /// While searching, the best matches come first. Otherwise the list is shown in order, or
+    /// from the end with `reverse`.
+    fn visible_indices(&self) -> Vec<usize> {
+        let Some(search) = &self.search else {
+            let mut indices = self.sorted_indices();
+            if self.config.reverse {
+                indices.reverse();
             }
-            _ => (),
+            return indices;
         };
+        let mut matches: Vec<(usize, i64)> = self
+            .sorted_indices()
+            .into_iter()
+            .filter_map(|idx| {
+                search
+                    .score(&self.panes[idx], &self.config.tag_prefix)
+                    .map(|(score, _)| (idx, score))
+            })
+            .collect();
+        matches.sort_by_key(|&(_, score)| Reverse(score));
+        matches.into_iter().map(|(idx, _)| idx).collect()
+    }
 
-        should_render
+    fn selected_key(&self) -> Option<(u32, u32)> {
+        self.panes.get(self.selected).map(Pane::key)
     }
 
-    fn render(&mut self, _rows: usize, _cols: usize) {
-        println!(
-            "{}",
-            self.panes
-                .iter()
-                .enumerate()
-                .map(|(idx, pane)| {
-                    if idx == self.selected {
-                        pane.to_string().red().bold().to_string()
-                    } else {
-                        pane.to_string()
-                    }
-                })
-                .collect::<Vec<String>>()
-                .join("\n")
+    /// Select the bookmark with `key`, returning whether it is still in the list.
+    fn select_key(&mut self, key: (u32, u32)) -> bool {
+        match self.panes.iter().position(|p| p.key() == key) {
+            Some(idx) => {
+                self.selected = idx;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Select the bookmark of the focused pane, if it is bookmarked.
+    fn select_focused(&mut self) -> bool {
+        match self.focused_pane.as_ref().map(|p| p.pane_info.id) {
+            Some(pane_id) => self.select_pane_id(pane_id),
+            None => false,
+        }
+    }
+
+    /// Select the bookmark of `pane_id`, returning whether it is still bookmarked.
+    fn select_pane_id(&mut self, pane_id: u32) -> bool {
+        match self.panes.iter().position(|p| p.pane_info.id == pane_id) {
+            Some(idx) => {
+                self.selected = idx;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Keep the selection on a bookmark matching the search, moving to the first match when the
+    /// selected one got filtered out.
+    fn update_search_selection(&mut self) {
+        let visible = self.visible_indices();
+        let switcher = self.search.as_ref().is_some_and(|s| s.switcher);
+        if switcher || !visible.contains(&self.selected) {
+            self.selected = visible.first().copied().unwrap_or(self.selected);
+        }
+    }
+
+    /// Handle a key typed while editing the search query.
+    fn handle_search_key(&mut self, key: Key) -> bool {
+        let Some(search) = &mut self.search else {
+            return false;
+        };
+        match key {
+            Key::Esc | Key::Ctrl('c') => {
+                self.search = None;
+            }
+            Key::Char('\n') if search.switcher => {
+                if self.visible_indices().is_empty() {
+                    return false;
+                }
+                self.search = None;
+                return self.handle_action(Action::Focus);
+            }
+            Key::Char('\n') => {
+                search.editing = false;
+            }
+            Key::Backspace => {
+                search.query.pop();
+                self.update_search_selection();
+            }
+            Key::Char(c) if !c.is_control() => {
+                search.query.push(c);
+                self.update_search_selection();
+            }
+            Key::Down | Key::Char('\t') => {
+                self.move_selection(1);
+            }
+            Key::Up | Key::BackTab => {
+                self.move_selection(-1);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Handle a key typed while editing the note of the selected bookmark. `Enter` keeps the
+    /// note, an empty one removing it, and `Esc` leaves it as it was.
+    fn handle_note_key(&mut self, key: Key) -> bool {
+        let Some(input) = &mut self.note_input else {
+            return false;
+        };
+        match key {
+            Key::Esc | Key::Ctrl('c') => {
+                self.note_input = None;
+            }
+            Key::Char('\n') => {
+                let note = self.note_input.take().filter(|n| !n.trim().is_empty());
+                if let Some(pane) = self.panes.get_mut(self.selected) {
+                    pane.note = note.map(|n| n.trim().to_string());
+                    self.save();
+                }
+            }
+            Key::Backspace => {
+                input.pop();
+            }
+            Key::Char(c) if !c.is_control() => {
+                input.push(c);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn toggle_browse(&mut self) {
+        if self.browse.take().is_some() {
+            return;
+        }
+        let panes = match (&self.pane_manifest, &self.tab_info) {
+            (Some(pane_manifest), Some(tab_info)) => get_all_panes(pane_manifest, tab_info),
+            _ => Vec::new(),
+        };
+        self.browse = Some(Browse { panes, selected: 0 });
+    }
+
+    fn is_bookmarked(&self, pane_id: u32) -> bool {
+        self.panes.iter().any(|p| p.pane_info.id == pane_id)
+    }
+
+    /// Bookmark `pane` unless it already is. Returns whether it was added. Every way of adding
+    /// goes through here, so plugin panes, this plugin's own among them, are refused here too,
+    /// even though the focused pane and the browsed panes already leave them out.
+    fn add_pane(&mut self, pane: Pane) -> bool {
+        if pane.pane_info.is_plugin || self.is_bookmarked(pane.pane_info.id) {
+            return false;
+        }
+        self.persistence.forget_kept_delete(pane.key());
+        self.panes.push(self.tagged(pane));
+        self.sort_panes();
+        self.save();
+        true
+    }
+
+    /// Tag `pane` by the start of its title, see `parse_tag`.
+    fn tagged(&self, pane: Pane) -> Pane {
+        let tag = parse_tag(&pane.pane_info.title, &self.config.tag_prefix);
+        Pane { tag, ..pane }
+    }
+
+    /// Handle actions while browsing all panes: navigation, bookmarking and focusing the
+    /// selected pane. Everything else only applies to the bookmarks.
+    fn handle_browse_action(&mut self, action: Action) -> bool {
+        let selected = self
+            .browse
+            .as_ref()
+            .and_then(|browse| browse.panes.get(browse.selected))
+            .cloned();
+        match action {
+            Action::Browse => {
+                self.toggle_browse();
+                true
+            }
+            Action::Help => {
+                self.help = true;
+                true
+            }
+            Action::Add => {
+                if let Some(pane) = selected {
+                    self.message = if self.add_pane(pane.clone()) {
+                        Some(format!("Added {}", pane))
+                    } else {
+                        Some(format!("{} is already bookmarked", pane))
+                    };
+                }
+                true
+            }
+            Action::Focus => {
+                if let Some(pane) = selected {
+                    self.hide();
+                    self.focus(&pane.pane_info);
+                }
+                false
+            }
+            Action::GoToTab => {
+                if let Some(pane) = selected {
+                    self.hide();
+                    compat::switch_to_tab(pane.tab_info.position);
+                }
+                false
+            }
+            Action::Quit => {
+                self.hide();
+                false
+            }
+            Action::Down | Action::Up | Action::PageDown | Action::PageUp => {
+                self.handle_navigation(action)
+            }
+            _ => false,
+        }
+    }
+
+    /// Move the selection of the shown list, returning whether it should re-render.
+    fn handle_navigation(&mut self, action: Action) -> bool {
+        // A count typed before the motion repeats it, like `3j` in vim.
+        let count = self.pending_count.take().unwrap_or(1) as isize;
+        if self.list_len() == 0 {
+            return false;
+        }
+        let step = match action {
+            Action::Down => 1,
+            Action::Up => -1,
+            Action::PageDown => self.page_size(),
+            Action::PageUp => -self.page_size(),
+            _ => return false,
+        };
+        self.move_selection(count * step);
+        if self.browse.is_none() {
+            self.preview_selected();
+        }
+        true
+    }
+
+    /// With `preview_on_navigate`, mark the frame of the selected pane for `PREVIEW_SECS`, so it
+    /// can be found on screen before switching to it. Marks are at most `PREVIEW_THROTTLE`
+    /// apart, so holding `j` doesn't rename every pane passed. Returns whether a mark was issued.
+    fn preview_selected(&mut self) -> bool {
+        if !self.config.preview_on_navigate {
+            return false;
+        }
+        if (self.preview.as_ref()).is_some_and(|p| p.at.elapsed() < PREVIEW_THROTTLE) {
+            return false;
+        }
+        let Some(pane) = self.panes.get(self.selected).filter(|p| !p.closed) else {
+            return false;
+        };
+        let (pane_id, title) = (pane.pane_info.id, pane.pane_info.title.clone());
+        self.end_preview();
+        compat::mark_pane(pane_id, PREVIEW_NAME);
+        set_timeout(PREVIEW_SECS);
+        self.preview = Some(Preview {
+            pane_id,
+            title,
+            at: Instant::now(),
+        });
+        true
+    }
+
+    /// Give the previewed pane its own title back.
+    fn end_preview(&mut self) {
+        if let Some(preview) = self.preview.take() {
+            compat::unmark_pane(preview.pane_id);
+        }
+    }
+
+    /// Handle a key press outside of the search input.
+    fn handle_key(&mut self, key: Key) -> bool {
+        let action = self.config.keymap.action(&key);
+        // Type-ahead and jumps take the next key as typed, whatever it is bound to.
+        if self.awaiting_type_ahead {
+            self.awaiting_type_ahead = false;
+            if let Key::Char(c) = key {
+                self.type_ahead(c);
+            }
+            return true;
+        }
+        if self.awaiting_jump.take().is_some() {
+            return match key {
+                Key::Char(c @ '1'..='9') => self.jump_to(c as usize - '1' as usize),
+                // Any other key cancels the jump.
+                _ => true,
+            };
+        }
+        // The counterpart of `l`: back out of whatever is open, as Esc does. Along the bar it
+        // moves left instead, leaving horizontal navigation to the keys that read as such.
+        if action == Some(Action::Back) && key != Key::Esc && !self.is_bar(self.rows) {
+            return self.handle_key(Key::Esc);
+        }
+        if self.help {
+            // The overview covers the list, so only closing it does anything.
+            if key == Key::Esc || action == Some(Action::Help) {
+                self.help = false;
+                return true;
+            }
+            return false;
+        }
+        if self.details {
+            // Like the help, the details cover the list, so only scrolling or closing them works.
+            if key == Key::Esc || action == Some(Action::Details) {
+                self.details = false;
+                return true;
+            }
+            return match action {
+                Some(Action::Down) => self.scroll_details(1),
+                Some(Action::Up) => self.scroll_details(-1),
+                _ => false,
+            };
+        }
+        if let Some((bookmarks, preset)) = self.confirm_replace.take() {
+            return self.finish_replace(key, bookmarks, preset);
+        }
+        if key == Key::Esc && self.browse.is_none() && self.search.is_none() {
+            if let Some(added) = self.adding.take() {
+                self.message = Some(format!("Added {} panes", added));
+                return true;
+            }
+            if !self.marked.is_empty() {
+                self.marked.clear();
+                return true;
+            }
+        }
+        if self.config.jump_leader == Some(key) && self.browse.is_none() {
+            self.pending_count = None;
+            self.awaiting_jump = Some(Instant::now());
+            self.message = Some("Jump to pane 1-9".to_string());
+            set_timeout(JUMP_TIMEOUT_SECS);
+            return true;
+        }
+        if let (Key::Char(c @ '0'..='9'), None) = (key, action) {
+            let digit = c.to_digit(10).unwrap_or(0) as usize;
+            let count = self.pending_count.unwrap_or(0) * 10 + digit;
+            if count > 0 {
+                self.pending_count = Some(count.min(MAX_COUNT));
+            }
+            return false;
+        }
+        // Along the bar, left and right move the selection too.
+        let action = match (key, action) {
+            (Key::Left, None) if self.is_bar(self.rows) => Some(Action::Up),
+            (_, Some(Action::Back)) if self.is_bar(self.rows) => Some(Action::Up),
+            (Key::Right, None) if self.is_bar(self.rows) => Some(Action::Down),
+            _ => action,
+        };
+        let Some(action) = action else {
+            self.pending_count = None;
+            return false;
+        };
+        let should_render = self.handle_action(action);
+        // Only motions consume the count, any other key discards it.
+        self.pending_count = None;
+        should_render
+    }
+
+    /// Select the next bookmark of the shown list whose title starts with `c`, ignoring case.
+    /// Repeating it cycles through all such bookmarks.
+    fn type_ahead(&mut self, c: char) {
+        let visible = self.visible_indices();
+        let start = visible
+            .iter()
+            .position(|&idx| idx == self.selected)
+            .map_or(0, |position| position + 1);
+        let c = c.to_lowercase().to_string();
+        let found = (0..visible.len())
+            .map(|offset| visible[(start + offset) % visible.len()])
+            .find(|&idx| self.panes[idx].title().to_lowercase().starts_with(&c));
+        match found {
+            Some(idx) => self.selected = idx,
+            None => self.message = Some(format!("No pane starting with {}", c)),
+        }
+    }
+
+    /// Select the bookmark at `position` of the shown list and switch to it.
+    fn jump_to(&mut self, position: usize) -> bool {
+        let Some(&idx) = self.visible_indices().get(position) else {
+            self.message = Some(format!("No pane {}", position + 1));
+            return true;
+        };
+        self.selected = idx;
+        self.handle_action(Action::Focus)
+    }
+
+    /// Handle a message sent with `zellij pipe`, returning whether the plugin should re-render.
+    fn handle_pipe_message(&mut self, pipe_message: &PipeMessage) -> bool {
+        let mutating = [IMPORT_PIPE, LOAD_PRESET_PIPE, RESET_CONFIG_PIPE];
+        if mutating.contains(&pipe_message.name.as_str()) && !self.ensure_unlocked() {
+            return true;
+        }
+        match pipe_message.name.as_str() {
+            SELECT_PIPE => {
+                let argument = pipe_message
+                    .args
+                    .get("index")
+                    .or(pipe_message.payload.as_ref());
+                // The index counts rows as shown, like jumps, see `visible_indices`.
+                let visible = self.visible_indices();
+                match argument.and_then(|a| parse_pipe_index(a, visible.len())) {
+                    Some(position) => {
+                        self.selected = visible[position];
+                        true
+                    }
+                    None => {
+                        debug_log!(
+                            self.config.debug,
+                            "ignored {} with index {:?}",
+                            SELECT_PIPE,
+                            argument
+                        );
+                        false
+                    }
+                }
+            }
+            IMPORT_PIPE => {
+                let payload = pipe_message.payload.as_deref().unwrap_or_default();
+                let file = match PersistFormat::Json.deserialize(payload) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        self.last_error = Some(format!("Could not import bookmarks: {}", e));
+                        return true;
+                    }
+                };
+                if pipe_message.args.get("mode").map(String::as_str) == Some("replace") {
+                    self.replace_list(file.bookmarks, None);
+                } else {
+                    self.import_bookmarks(file.bookmarks);
+                }
+                true
+            }
+            SAVE_PRESET_PIPE | LOAD_PRESET_PIPE => {
+                let mut name = pipe_message.payload.as_deref().unwrap_or_default().trim();
+                // Without a name the list is saved back to the preset it was loaded from.
+                if name.is_empty() && pipe_message.name == SAVE_PRESET_PIPE {
+                    name = self
+                        .persistence
+                        .active_preset
+                        .as_deref()
+                        .unwrap_or_default();
+                }
+                let name = name.to_string();
+                let issued = if pipe_message.name == SAVE_PRESET_PIPE {
+                    (self.persistence).save_preset(&name, &self.panes, self.pane_manifest.as_ref())
+                } else {
+                    self.persistence.load_preset(&name)
+                };
+                match issued {
+                    Ok(true) => return false,
+                    Ok(false) => {
+                        self.last_error = Some("Presets need persistence".to_string());
+                    }
+                    Err(e) => self.last_error = Some(e),
+                }
+                true
+            }
+            FOCUS_ALIAS_PIPE => {
+                let alias = pipe_message.payload.as_deref().unwrap_or_default().trim();
+                let tag = match alias.strip_prefix(self.config.tag_prefix.as_str()) {
+                    Some(_) => alias.to_string(),
+                    None => format!("{}{}", self.config.tag_prefix, alias),
+                };
+                if !self.focus_first(|p| p.tag.as_deref() == Some(tag.as_str())) {
+                    debug_log!(self.config.debug, "no open pane tagged {}", tag);
+                }
+                false
+            }
+            FOCUS_MATCH_PIPE => {
+                let pattern = pipe_message.payload.as_deref().unwrap_or_default().trim();
+                if pattern.is_empty()
+                    || !self.focus_first(|p| matches_pattern(&p.to_string(), pattern))
+                {
+                    debug_log!(self.config.debug, "no open pane matching {:?}", pattern);
+                }
+                false
+            }
+            EXPORT_LAYOUT_PIPE => {
+                let path = pipe_message.payload.as_deref().unwrap_or_default().trim();
+                if path.is_empty() {
+                    self.last_error = Some("Export needs a path".to_string());
+                } else {
+                    let panes: Vec<Pane> = (self.sorted_indices().into_iter())
+                        .map(|idx| self.panes[idx].clone())
+                        .collect();
+                    let kdl = layout_export::layout_kdl(&panes);
+                    if !self.persistence.write_export(path, &kdl) {
+                        self.last_error =
+                            Some("Export needs persistence to write files".to_string());
+                    }
+                }
+                true
+            }
+            SWITCHER_PIPE => {
+                self.help = false;
+                self.browse = None;
+                self.search = Some(Search {
+                    query: String::new(),
+                    editing: true,
+                    switcher: true,
+                });
+                self.update_search_selection();
+                show_self(true);
+                true
+            }
+            RESET_CONFIG_PIPE => {
+                self.reset_config();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Hide the plugin and switch to the first open bookmark for which `matches` holds, returning
+    /// whether there was one.
+    fn focus_first(&mut self, matches: impl Fn(&Pane) -> bool) -> bool {
+        let pane = (self.sorted_indices().into_iter())
+            .map(|idx| &self.panes[idx])
+            .find(|p| !p.closed && matches(p));
+        let Some(pane_info) = pane.map(|p| p.pane_info.clone()) else {
+            return false;
+        };
+        self.hide();
+        self.focus(&pane_info);
+        true
+    }
+
+    /// Revert the settings changed at runtime to their configured values. Nothing is persisted,
+    /// the next save stores the reverted view as usual.
+    fn reset_config(&mut self) {
+        if let Some(sort_mode) = self.configured.sort_mode {
+            self.config.sort_mode = sort_mode;
+        }
+        if let Some(collapse_tab_labels) = self.configured.collapse_tab_labels {
+            self.config.collapse_tab_labels = collapse_tab_labels;
+        }
+        self.config.show_ids = self.configured_show_ids;
+        self.locked = self.config.locked;
+        self.help = false;
+        self.scroll = 0;
+        self.message = Some("Settings reset".to_string());
+        debug_log!(self.config.debug, "settings reset to the configured values");
+    }
+
+    /// Merge imported bookmarks into the list, reporting how many were new.
+    fn import_bookmarks(&mut self, bookmarks: Vec<PaneBookmark>) {
+        let queued = self.persistence.merge_bookmarks(bookmarks, &self.panes);
+        let before = self.panes.len();
+        self.update_panes();
+        let matched = self.panes.len().saturating_sub(before);
+        self.save();
+        self.message = Some(format!(
+            "Imported {} bookmarks ({} panes found)",
+            queued, matched
+        ));
+    }
+
+    /// Ask to replace the list (apart from the base list) by `bookmarks`, or replace it right away
+    /// when nothing would be dropped. The plugin is shown to ask, since a pipe or a loaded preset
+    /// may start the replace while it is hidden. `preset` names the preset the bookmarks come
+    /// from, which becomes the active one.
+    fn replace_list(&mut self, bookmarks: Vec<PaneBookmark>, preset: Option<String>) {
+        let dropped = self.panes.iter().filter(|p| !p.base).count()
+            + (self.persistence.pending_bookmarks.iter())
+                .filter(|b| !b.base)
+                .count();
+        if dropped > 0 {
+            self.message = Some(format!(
+                "Replace the list, dropping {} bookmarks? (y/n)",
+                dropped
+            ));
+            self.confirm_replace = Some((bookmarks, preset));
+            show_self(true);
+        } else {
+            self.persistence.active_preset = preset;
+            self.import_bookmarks(bookmarks);
+        }
+    }
+
+    /// Replace the list (apart from the base list) by imported bookmarks, once confirmed.
+    fn finish_replace(
+        &mut self,
+        key: Key,
+        bookmarks: Vec<PaneBookmark>,
+        preset: Option<String>,
+    ) -> bool {
+        if key != Key::Char('y') {
+            self.message = Some("Replace cancelled".to_string());
+            return true;
+        }
+        self.panes.retain(|p| p.base);
+        self.persistence.pending_bookmarks.retain(|b| b.base);
+        self.clamp_selection();
+        self.persistence.active_preset = preset;
+        self.import_bookmarks(bookmarks);
+        true
+    }
+
+    /// Keep the selection within the bounds of the list after panes were removed.
+    fn clamp_selection(&mut self) {
+        if self.selected >= self.panes.len() {
+            self.selected = self.panes.len().saturating_sub(1);
+        }
+    }
+
+    fn page_size(&self) -> isize {
+        let rows = match self.config.max_rows {
+            Some(max_rows) => self.rows.min(max_rows),
+            None => self.rows,
+        };
+        rows.max(1) as isize
+    }
+
+    /// Keep pinned panes first, in the order they were pinned. Otherwise `panes` keeps the manual
+    /// order, which is saved; the sort mode only orders the list as shown, see `sorted_indices`.
+    fn sort_panes(&mut self) {
+        self.panes.sort_by_key(|p| !p.pinned);
+    }
+
+    /// Indices of the bookmarks in the order of the sort mode. Pinned panes always come first,
+    /// in the order they were pinned.
+    fn sorted_indices(&self) -> Vec<usize> {
+        let sort_mode = self.config.sort_mode;
+        let mut indices: Vec<usize> = (0..self.panes.len()).collect();
+        indices.sort_by(|&x, &y| {
+            let (x, y) = (&self.panes[x], &self.panes[y]);
+            match (x.pinned, y.pinned) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Less,
+                (false, true) => Ordering::Greater,
+                (false, false) => match sort_mode {
+                    SortMode::Tab => x.tab_info.position.cmp(&y.tab_info.position),
+                    SortMode::Manual => Ordering::Equal,
+                    SortMode::Alpha => x
+                        .to_string()
+                        .to_lowercase()
+                        .cmp(&y.to_string().to_lowercase()),
+                    SortMode::Recent => {
+                        let focused = |p: &Pane| self.last_focused.get(&p.pane_info.id).copied();
+                        focused(y).cmp(&focused(x))
+                    }
+                },
+            }
+        });
+        indices
+    }
+
+    /// Insert a copy of the selected bookmark right after it and select the copy. The copy gets
+    /// its own `copy` number, so it is selected, marked and restored apart from the original.
+    fn duplicate_selected(&mut self) {
+        let Some(pane) = self.panes.get(self.selected).cloned() else {
+            return;
+        };
+        let copy = (self.panes.iter())
+            .filter(|p| p.pane_info.id == pane.pane_info.id)
+            .map(|p| p.copy)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        self.selected += 1;
+        self.panes.insert(self.selected, Pane { copy, ..pane });
+        self.save();
+    }
+
+    /// Point the selected bookmark at the focused pane, keeping its place in the list. Other
+    /// bookmarks of the focused pane are removed.
+    fn swap_selected_with_focused(&mut self) {
+        let Some(focused_pane) = self.focused_pane.clone() else {
+            self.message = Some("No focused pane to swap in".to_string());
+            return;
+        };
+        let Some(slot) = self.panes.get(self.selected) else {
+            return;
+        };
+        let pane = Pane {
+            pinned: slot.pinned,
+            ..self.tagged(focused_pane)
+        };
+        let pane_id = pane.pane_info.id;
+        self.panes[self.selected] = pane;
+        let selected = self.selected;
+        let mut idx = 0;
+        self.panes.retain(|p| {
+            let keep = idx == selected || p.pane_info.id != pane_id;
+            idx += 1;
+            keep
+        });
+        self.sort_panes();
+        self.select_pane_id(pane_id);
+        self.save();
+    }
+
+    /// Move the selected bookmark to `target`, clamped to the list, keeping it selected. Only
+    /// possible in manual sort mode; pinned bookmarks still stay above the others.
+    fn move_selected_to(&mut self, target: usize) {
+        if self.config.sort_mode != SortMode::Manual {
+            self.message = Some("Reordering requires sort_mode manual".to_string());
+            return;
+        }
+        if self.selected >= self.panes.len() {
+            return;
+        }
+        let pane = self.panes.remove(self.selected);
+        let key = pane.key();
+        self.panes.insert(target.min(self.panes.len()), pane);
+        self.sort_panes();
+        self.select_key(key);
+        self.save();
+    }
+
+    /// Remove every unpinned pane that is no longer present in the pane manifest and return the
+    /// removed panes.
+    fn remove_stale_panes(&mut self) -> Vec<Pane> {
+        let Some(pane_manifest) = &self.pane_manifest else {
+            return Vec::new();
+        };
+        let live_ids: HashSet<u32> = pane_manifest
+            .panes
+            .values()
+            .flatten()
+            .filter(|p| !p.is_plugin)
+            .map(|p| p.id)
+            .collect();
+        let (kept, removed) = std::mem::take(&mut self.panes)
+            .into_iter()
+            .partition(|p| p.pinned || live_ids.contains(&p.pane_info.id));
+        self.panes = kept;
+        self.clamp_selection();
+        removed
+    }
+
+    /// Briefly highlight a newly added pane, when the plugin stays open to show it.
+    fn highlight_added(&mut self, pane_id: u32) {
+        if self.config.hide_after_add {
+            return;
+        }
+        let highlight = Duration::from_secs_f64(JUST_ADDED_HIGHLIGHT_SECS);
+        self.just_added = Some((pane_id, Instant::now() + highlight));
+        set_timeout(JUST_ADDED_HIGHLIGHT_SECS);
+    }
+
+    /// Hide the plugin after an add, unless configured to stay open. Returns whether it was
+    /// hidden.
+    fn hide_after_add(&mut self) -> bool {
+        if self.config.hide_after_add {
+            self.hide();
+        }
+        self.config.hide_after_add
+    }
+
+    /// Start waiting `auto_hide_ms` for the next key, if set.
+    fn restart_auto_hide(&mut self) {
+        if let Some(auto_hide) = self.config.auto_hide {
+            self.last_activity = Some(Instant::now());
+            set_timeout(auto_hide.as_secs_f64());
+        }
+    }
+
+    /// Hide the plugin once no key was pressed for `auto_hide_ms`. Timer events don't tell which
+    /// timeout fired, so it is judged by the time since the last key.
+    fn check_auto_hide(&mut self) {
+        let (Some(auto_hide), Some(last_activity)) = (self.config.auto_hide, self.last_activity)
+        else {
+            return;
+        };
+        if last_activity.elapsed() >= auto_hide {
+            self.last_activity = None;
+            self.hide();
+        }
+    }
+
+    /// Start the `save_interval_ms` timer again once it has run out. Every timer event writes
+    /// unsaved changes, so the timer only has to keep firing.
+    fn restart_save_interval(&mut self) {
+        let Some(interval) = self.config.save_interval else {
+            return;
+        };
+        if self
+            .last_interval_save
+            .is_none_or(|started| started.elapsed() >= interval)
+        {
+            self.last_interval_save = Some(Instant::now());
+            set_timeout(interval.as_secs_f64());
+        }
+    }
+
+    /// Switch to the pane, zooming it with `zoom_on_focus`. The zoom toggles the focused pane,
+    /// which the focus call issued first has made this one.
+    fn focus(&mut self, pane_info: &PaneInfo) {
+        compat::focus_pane(pane_info.id, self.config.focus_float_if_hidden);
+        if is_collapsed_in_stack(pane_info) {
+            self.expanding = Some(pane_info.id);
+        }
+        if self.config.zoom_on_focus && !pane_info.is_fullscreen {
+            toggle_focus_fullscreen();
+        }
+    }
+
+    /// Focus a stacked pane again if the focus left it collapsed, once the next `PaneUpdate`
+    /// tells. Returns whether it was focused again; where focusing already expands a stacked
+    /// pane, the plain focus was enough.
+    fn expand_stacked(&mut self) -> bool {
+        let Some(pane_id) = self.expanding.take() else {
+            return false;
+        };
+        let collapsed = (self.pane_manifest.iter())
+            .flat_map(|manifest| manifest.panes.values().flatten())
+            .any(|p| p.id == pane_id && !p.is_plugin && is_collapsed_in_stack(p));
+        if collapsed {
+            debug_log!(
+                self.config.debug,
+                "pane {} still stacked, focusing again",
+                pane_id
+            );
+            compat::focus_pane(pane_id, self.config.focus_float_if_hidden);
+        }
+        collapsed
+    }
+
+    /// Hide the plugin, writing out unsaved changes first so they aren't lost if the plugin is
+    /// closed before the debounced save runs. Changes made before the saved bookmarks were read
+    /// can't be written yet; they stay pending and are written once the bookmarks are loaded.
+    fn hide(&mut self) {
+        self.end_preview();
+        self.flush();
+        if self.dirty {
+            debug_log!(
+                self.config.debug,
+                "hidden with unsaved changes, saving once loaded"
+            );
+        }
+        hide_self();
+        self.hid = true;
+    }
+
+    /// Whether the list may be changed, telling why not while it is locked. Every change asked
+    /// for by a key, a pipe message or a command result checks here first.
+    fn ensure_unlocked(&mut self) -> bool {
+        if self.locked {
+            self.message = Some("The list is locked".to_string());
+        }
+        !self.locked
+    }
+
+    /// Perform a key bound action, returning whether the plugin should re-render.
+    fn handle_action(&mut self, action: Action) -> bool {
+        if action.is_mutating() && !self.ensure_unlocked() {
+            return true;
+        }
+        if self.browse.is_some() {
+            return self.handle_browse_action(action);
+        }
+        if self.panes.is_empty() && action.needs_bookmarks() {
+            return false;
+        }
+        // A search matching nothing leaves the selection on a bookmark that isn't shown.
+        if action.needs_selection() && !self.visible_indices().contains(&self.selected) {
+            return false;
+        }
+        let confirm_ignored = self.confirm_ignored.take();
+        // Moves are along the list as shown, which runs backwards with `reverse`.
+        let action = match action {
+            Action::MoveDown if self.config.reverse => Action::MoveUp,
+            Action::MoveUp if self.config.reverse => Action::MoveDown,
+            Action::MoveToTop if self.config.reverse => Action::MoveToBottom,
+            Action::MoveToBottom if self.config.reverse => Action::MoveToTop,
+            action => action,
+        };
+        let mut should_render = false;
+        match action {
+            Action::Quit if self.search.is_some() => {
+                // Leaving the search keeps the selected bookmark selected.
+                self.search = None;
+                should_render = true;
+            }
+            Action::Search => {
+                self.search = Some(Search {
+                    query: String::new(),
+                    editing: true,
+                    switcher: false,
+                });
+                should_render = true;
+            }
+            Action::AddAll => {
+                let current_pane_ids: HashSet<u32> =
+                    self.panes.iter().map(|p| p.pane_info.id).collect();
+                let new_panes: Vec<Pane> = match (&self.pane_manifest, &self.tab_info) {
+                    (Some(pane_manifest), Some(tab_info)) => get_all_panes(pane_manifest, tab_info)
+                        .into_iter()
+                        .filter(|p| !current_pane_ids.contains(&p.pane_info.id))
+                        .filter(|p| !self.config.is_ignored(&p.pane_info.title))
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                if new_panes.is_empty() {
+                    // Stay open so it is clear why nothing happened.
+                    self.message = Some("All panes already bookmarked".to_string());
+                    return true;
+                }
+                for pane in new_panes {
+                    self.add_pane(pane);
+                }
+                should_render = !self.hide_after_add();
+            }
+            Action::Add
+                if self.focused_pane.as_ref().is_some_and(|p| {
+                    let id = p.pane_info.id;
+                    self.config.is_ignored(&p.pane_info.title)
+                        && !self.is_bookmarked(id)
+                        && confirm_ignored != Some(id)
+                }) =>
+            {
+                if let Some(pane) = &self.focused_pane {
+                    self.message = Some(format!(
+                        "{} matches ignore_titles, add it again to add it anyway",
+                        pane
+                    ));
+                    self.confirm_ignored = Some(pane.pane_info.id);
+                }
+                should_render = true;
+            }
+            Action::Add => {
+                match self.focused_pane.clone() {
+                    // Stay open so the next pane can be added right away. Panes that are
+                    // already bookmarked are skipped by `add_pane`.
+                    Some(pane) if self.adding.is_some() => {
+                        let pane_id = pane.pane_info.id;
+                        if self.add_pane(pane.clone()) {
+                            self.highlight_added(pane_id);
+                            self.adding = self.adding.map(|added| added + 1);
+                        } else {
+                            self.message = Some(format!("{} is already bookmarked", pane));
+                        }
+                    }
+                    // Stay open on the existing bookmark, so it is clear why nothing was added.
+                    Some(pane) if self.is_bookmarked(pane.pane_info.id) => {
+                        self.select_pane_id(pane.pane_info.id);
+                        self.message = Some("Already bookmarked".to_string());
+                    }
+                    Some(pane) => {
+                        let pane_id = pane.pane_info.id;
+                        if self.add_pane(pane) {
+                            self.highlight_added(pane_id);
+                        }
+                        if self.hide_after_add() {
+                            return false;
+                        }
+                    }
+                    // E.g. in a session that has no panes yet
+                    None => self.message = Some("No focused pane to add".to_string()),
+                }
+                should_render = true;
+            }
+            Action::Delete if self.panes.get(self.selected).is_some_and(|p| p.base) => {
+                self.message = Some("Panes of the base list can't be removed".to_string());
+                should_render = true;
+            }
+            Action::Delete => {
+                if self.selected < self.panes.len() {
+                    let visible = self.visible_indices();
+                    let position = visible.iter().position(|&idx| idx == self.selected);
+                    let pane = self.panes.remove(self.selected);
+                    self.removed(vec![pane]);
+                    // Land on the row shown above the removed one, or on the new first row.
+                    let visible = self.visible_indices();
+                    let position = position.unwrap_or(0).saturating_sub(1);
+                    self.selected = visible.get(position).copied().unwrap_or(0);
+                }
+                should_render = true;
+            }
+
+            Action::CopyCommand => {
+                if let Some(pane) = self.panes.get(self.selected) {
+                    // The result reports whether the copy worked, see `compat::clipboard_result`.
+                    let (text, what) = clipboard_text(pane);
+                    if self.persistence.can_run_commands() {
+                        compat::copy_to_clipboard(text, what);
+                    } else if !self.persistence.enabled {
+                        self.last_error =
+                            Some("Copying runs a command, which needs persistence".to_string());
+                    } else {
+                        self.last_error =
+                            Some("Copying needs the permission to run commands".to_string());
+                    }
+                }
+                should_render = true;
+            }
+            Action::Mark => {
+                if let Some(key) = self.selected_key() {
+                    if !self.marked.remove(&key) {
+                        self.marked.insert(key);
+                    }
+                }
+                should_render = true;
+            }
+            Action::DeleteMarked => {
+                let (removed, kept): (Vec<Pane>, Vec<Pane>) = std::mem::take(&mut self.panes)
+                    .into_iter()
+                    .partition(|p| !p.base && self.marked.contains(&p.key()));
+                self.panes = kept;
+                self.marked.clear();
+                self.clamp_selection();
+                self.message = Some(format!("Removed {} panes", removed.len()));
+                if !removed.is_empty() {
+                    self.removed(removed);
+                }
+                should_render = true;
+            }
+            Action::Compact => {
+                let removed = self.remove_stale_panes();
+                self.message = Some(format!("Removed {} stale bookmarks", removed.len()));
+                if !removed.is_empty() {
+                    self.removed(removed);
+                }
+                should_render = true;
+            }
+            Action::Pin => {
+                self.toggle_pin_selected();
+                self.save();
+                should_render = true;
+            }
+            Action::ToggleIds => {
+                self.config.show_ids = !self.config.show_ids;
+                should_render = true;
+            }
+            Action::PinAll => {
+                let pin = !self.panes.iter().all(|p| p.pinned);
+                let mut changed = 0;
+                for pane in self.panes.iter_mut().filter(|p| p.pinned != pin) {
+                    pane.pinned = pin;
+                    changed += 1;
+                }
+                let selected_key = self.selected_key();
+                self.sort_panes();
+                if let Some(key) = selected_key {
+                    self.select_key(key);
+                }
+                self.save();
+                let verb = if pin { "Pinned" } else { "Unpinned" };
+                self.message = Some(format!("{} {} panes", verb, changed));
+                should_render = true;
+            }
+            Action::Reload => {
+                if !self.persistence.reload_from_disk() {
+                    self.message =
+                        Some("Nothing to reload, persistence is off or still loading".to_string());
+                    should_render = true;
+                }
+            }
+            Action::AddMode => {
+                self.adding = match self.adding {
+                    Some(added) => {
+                        self.message = Some(format!("Added {} panes", added));
+                        None
+                    }
+                    None => Some(0),
+                };
+                should_render = true;
+            }
+            Action::TypeAhead => {
+                self.awaiting_type_ahead = true;
+                self.message = Some("Go to pane starting with...".to_string());
+                should_render = true;
+            }
+            Action::Note => {
+                if let Some(pane) = self.panes.get(self.selected) {
+                    self.note_input = Some(pane.note.clone().unwrap_or_default());
+                    should_render = true;
+                }
+            }
+            Action::TogglePersist => {
+                if let Some(pane) = self.panes.get_mut(self.selected) {
+                    pane.ephemeral = !pane.ephemeral;
+                    self.save();
+                }
+                should_render = true;
+            }
+            Action::Duplicate => {
+                self.duplicate_selected();
+                should_render = true;
+            }
+            Action::Swap => {
+                self.swap_selected_with_focused();
+                should_render = true;
+            }
+            Action::CycleSort => {
+                self.config.sort_mode = self.config.sort_mode.next();
+                // The sort mode is saved with the list.
+                self.save();
+                self.message = Some(format!("Sort mode: {}", self.config.sort_mode.name()));
+                should_render = true;
+            }
+            Action::Lock => {
+                self.locked = !self.locked;
+                should_render = true;
+            }
+            Action::Help => {
+                self.help = true;
+                should_render = true;
+            }
+            Action::Details => {
+                self.details = true;
+                self.detail_scroll = 0;
+                should_render = true;
+            }
+            Action::MoveDown => {
+                self.move_selected_to(self.selected + 1);
+                should_render = true;
+            }
+            Action::MoveUp => {
+                self.move_selected_to(self.selected.saturating_sub(1));
+                should_render = true;
+            }
+            Action::MoveToTop => {
+                self.move_selected_to(0);
+                should_render = true;
+            }
+            Action::MoveToBottom => {
+                self.move_selected_to(self.panes.len());
+                should_render = true;
+            }
+
+            Action::Quit => {
+                self.hide();
+            }
+            // Handled as Esc in `handle_key`.
+            Action::Back => {}
+
+            Action::Down | Action::Up | Action::PageDown | Action::PageUp => {
+                should_render = self.handle_navigation(action);
+            }
+            Action::Browse => {
+                self.toggle_browse();
+                should_render = true;
+            }
+            Action::Focus => {
+                let pane = self.panes.get(self.selected);
+
+                if let Some(pane_info) = pane.filter(|p| !p.closed).map(|p| p.pane_info.clone()) {
+                    self.hide();
+                    // TODO: This has a bug on macOS with hidden panes
+                    self.focus(&pane_info);
+                }
+            }
+            Action::GoToTab => {
+                // Only switch tabs, leaving the focus within the tab as it is.
+                let pane = self.panes.get(self.selected);
+                if let Some(position) = pane.filter(|p| !p.closed).map(|p| p.tab_info.position) {
+                    self.hide();
+                    compat::switch_to_tab(position);
+                }
+            }
+        }
+        should_render
+    }
+
+    /// The label of a bookmark. With `collapse_tab_labels` the tab name is blanked out when the
+    /// previous row is in the same tab.
+    fn pane_label(&self, pane: &Pane, previous: Option<&Pane>) -> String {
+        let same_tab = previous.is_some_and(|p| p.tab_info.name == pane.tab_info.name);
+        let tab = if self.config.collapse_tab_labels && same_tab {
+            " ".repeat(pane.tab_info.name.chars().count())
+        } else {
+            pane.tab_info.name.clone()
+        };
+        format!("{} | {}", tab, self.capped_title(pane))
+    }
+
+    /// The pane title, with a path shown as `cwd_display` says and cut to `max_title_len` if
+    /// set.
+    fn capped_title(&self, pane: &Pane) -> String {
+        let title = self.shown_title(pane);
+        match self.config.max_title_len {
+            Some(max) => truncate(&title, max),
+            None => title,
+        }
+    }
+
+    /// The pane title, with a path shown as `cwd_display` says.
+    fn shown_title(&self, pane: &Pane) -> String {
+        let title = pane.title();
+        if !is_path(title) {
+            return title.to_string();
+        }
+        (self.config.cwd_display).format(title, self.config.home_dir.as_deref())
+    }
+
+    /// `✓ ` before marked bookmarks, and padding before the others while any are marked.
+    fn mark_prefix(&self, pane: &Pane) -> &'static str {
+        if self.marked.is_empty() {
+            ""
+        } else if self.marked.contains(&pane.key()) {
+            "✓ "
+        } else {
+            "  "
+        }
+    }
+
+    /// The status glyph shown before a bookmark with `show_status`, followed by a space, or
+    /// nothing.
+    fn status_prefix(&self, pane: &Pane) -> String {
+        if !self.config.show_status {
+            return String::new();
+        }
+        match status_glyph(&pane.pane_info) {
+            Some(glyph) => format!("{} ", glyph),
+            None => String::new(),
+        }
+    }
+
+    /// The icon shown before a bookmark with `icons`, followed by a space, or nothing.
+    fn icon_prefix(&self, pane: &Pane) -> String {
+        if !self.config.icons {
+            return String::new();
+        }
+        match self.config.icon_map.icon(&pane.pane_info) {
+            Some(icon) => format!("{} ", icon),
+            None => String::new(),
+        }
+    }
+
+    /// Tells panes with the same title in the same tab apart, e.g. the ` (2)` of `zsh (2)`.
+    fn title_suffix(&self, pane: &Pane) -> String {
+        let tab_panes = self
+            .pane_manifest
+            .as_ref()
+            .and_then(|m| m.panes.get(&pane.tab_info.position));
+        match tab_panes.and_then(|tab_panes| same_title_index(&pane.pane_info, tab_panes)) {
+            Some(idx) => format!(" ({})", idx + 1),
+            None => String::new(),
+        }
+    }
+
+    /// Style the selected row according to `selection_style`.
+    fn paint_selected(&self, text: &str) -> String {
+        match self.config.selection_style {
+            SelectionStyle::Fg => self.paint(text, |s| s.red().bold().to_string()),
+            SelectionStyle::Bg => self.paint(text, |s| s.bold().on_bright_black().to_string()),
+            SelectionStyle::Invert => self.paint(text, |s| s.bold().reversed().to_string()),
+            // The bar is added by `with_gutter`, the bar layout has no room for it.
+            SelectionStyle::Gutter => self.paint(text, |s| s.bold().to_string()),
+        }
+    }
+
+    /// Apply `style` to `text`, unless colors are disabled.
+    fn paint(&self, text: &str, style: impl Fn(&str) -> String) -> String {
+        if self.config.color {
+            style(text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Without colors the selection is shown with the `selection_marker`, so prefix every line
+    /// with either the marker or padding of the same width.
+    fn mark_selection(&self, line: String, selected: bool) -> String {
+        if self.config.color {
+            return line;
+        }
+        let marker = &self.config.selection_marker;
+        if selected {
+            format!("{}{}", marker, line)
+        } else {
+            format!("{}{}", " ".repeat(marker.chars().count()), line)
+        }
+    }
+
+    /// Columns taken before each row by the selection marker or the gutter.
+    fn selection_width(&self) -> usize {
+        if !self.config.color {
+            self.config.selection_marker.chars().count()
+        } else if self.config.selection_style == SelectionStyle::Gutter {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// With the `gutter` selection style, a column before each row holding a bar on the
+    /// selected one.
+    fn with_gutter(&self, line: String, selected: bool) -> String {
+        if !self.config.color || self.config.selection_style != SelectionStyle::Gutter {
+            return line;
+        }
+        if selected {
+            format!(
+                "{}{}",
+                self.paint("▎", |s| s.red().bold().to_string()),
+                line
+            )
+        } else {
+            format!(" {}", line)
+        }
+    }
+
+    /// Save the list after `panes` were removed. Without `delete_persists` they are kept in the
+    /// saved file instead, so the removal only lasts for this session.
+    fn removed(&mut self, panes: Vec<Pane>) {
+        if self.config.delete_persists {
+            self.save();
+            return;
+        }
+        let kept = panes.iter().filter(|p| !p.base && !p.ephemeral);
+        let kept = kept.map(|p| (p.key(), PaneBookmark::from(p)));
+        self.persistence.kept_deletes.extend(kept);
+    }
+
+    /// Mark the bookmarks as changed and schedule writing them to disk.
+    fn save(&mut self) {
+        if !self.config.persistence {
+            return;
+        }
+        if !self.dirty {
+            self.dirty = true;
+            set_timeout(SAVE_DEBOUNCE_SECS);
+        }
+    }
+
+    /// Write unsaved changes to disk, returning whether a save was issued. The changes stay
+    /// pending while the saved bookmarks haven't been loaded yet.
+    fn flush(&mut self) -> bool {
+        if !self.dirty {
+            return false;
+        }
+        match self.persistence.save_to_disk(
+            &self.panes,
+            self.pane_manifest.as_ref(),
+            self.view_preferences(),
+        ) {
+            Ok(true) => {
+                self.dirty = false;
+                true
+            }
+            Ok(false) => false,
+            Err(e) => {
+                self.last_error = Some(format!("Failed to save bookmarks: {}", e));
+                false
+            }
+        }
+    }
+
+    /// The view to save with the list. Settings still at their configured value are left out,
+    /// so a later change of the configuration isn't overridden by the session file.
+    fn view_preferences(&self) -> ViewPreferences {
+        let sort_mode = self.config.sort_mode;
+        let collapse_tab_labels = self.config.collapse_tab_labels;
+        ViewPreferences {
+            sort_mode: (self.configured.sort_mode != Some(sort_mode)).then_some(sort_mode),
+            collapse_tab_labels: (self.configured.collapse_tab_labels != Some(collapse_tab_labels))
+                .then_some(collapse_tab_labels),
+            scroll: Some(self.scroll),
+        }
+    }
+
+    /// Restore how the list was last viewed, once it has been read from disk.
+    fn apply_view_preferences(&mut self) {
+        let Some(view) = self.persistence.view.take() else {
+            return;
+        };
+        if let Some(sort_mode) = view.sort_mode {
+            self.config.sort_mode = sort_mode;
+        }
+        if let Some(collapse_tab_labels) = view.collapse_tab_labels {
+            self.config.collapse_tab_labels = collapse_tab_labels;
+        }
+        // Clamped to the list on the next render, see `scroll_window`.
+        if let Some(scroll) = view.scroll {
+            self.scroll = scroll;
+        }
+    }
+
+    fn toggle_pin_selected(&mut self) {
+        let Some(pane) = self.panes.get_mut(self.selected) else {
+            return;
+        };
+        pane.pinned = !pane.pinned;
+        let key = pane.key();
+        self.sort_panes();
+        self.select_key(key);
+    }
+
+    /// Everything `render` prints, kept free of side effects so it can be checked on its own.
+    fn render_to_string(&self, rows: usize, cols: usize) -> String {
+        if self.help {
+            return self.help_lines().join("\n");
+        }
+        if self.details {
+            return self.details_window(rows, cols).join("\n");
+        }
+        if self.is_bar(rows) {
+            return self.bar_line(cols);
+        }
+        let mut lines = Vec::new();
+        if self.locked {
+            lines.push(self.paint("🔒 locked", |s| s.dimmed().to_string()));
+        }
+        if let Some(last_error) = &self.last_error {
+            lines.push(self.paint(last_error, |s| s.red().to_string()));
+        }
+        let pending = self.persistence.pending_count();
+        if pending > 0 {
+            let line = format!("{} pending", pending);
+            lines.push(self.paint(&line, |s| s.dimmed().to_string()));
+        }
+        if let Some(added) = self.adding {
+            let line = format!("Adding panes: added {} so far (Esc to stop)", added);
+            lines.push(self.paint(&line, |s| s.green().to_string()));
+        }
+        if let Some(message) = &self.message {
+            lines.push(self.paint(message, |s| s.dimmed().to_string()));
+        }
+        let list = match &self.browse {
+            Some(browse) => {
+                let header = "All panes (b to return to bookmarks)";
+                lines.push(self.paint(header, |s| s.dimmed().to_string()));
+                self.browse_lines(browse, cols)
+            }
+            None => {
+                if let Some(search) = &self.search {
+                    let cursor = if search.editing { "_" } else { "" };
+                    lines.push(format!("/{}{}", search.query, cursor));
+                }
+                if let Some(note) = self.note_line() {
+                    lines.push(note);
+                }
+                if let Some(hint) = self.empty_hint() {
+                    lines.push(self.paint(&hint, |s| s.dimmed().to_string()));
+                }
+                self.bookmark_lines(cols)
+            }
+        };
+        match self.scroll_window(rows) {
+            Some((offset, height)) => lines.extend(list.into_iter().skip(offset).take(height)),
+            None => lines.extend(list),
+        }
+        if self.config.show_footer {
+            let footer = truncate(&self.footer(), cols);
+            lines.push(self.paint(&footer, |s| s.dimmed().to_string()));
+        }
+        lines.join("\n")
+    }
+
+    /// The active modes and settings, shown below the list with `show_footer`, e.g.
+    /// `[sort:manual] [reverse] [locked]`.
+    fn footer(&self) -> String {
+        let mut modes = vec![format!("[sort:{}]", self.config.sort_mode.name())];
+        if self.config.reverse {
+            modes.push("[reverse]".to_string());
+        }
+        if self.config.collapse_tab_labels {
+            modes.push("[collapsed]".to_string());
+        }
+        if self.locked {
+            modes.push("[locked]".to_string());
+        }
+        if self.browse.is_some() {
+            modes.push("[browse]".to_string());
+        }
+        if self.search.is_some() {
+            modes.push("[search]".to_string());
+        }
+        if self.adding.is_some() {
+            modes.push("[adding]".to_string());
+        }
+        if !self.marked.is_empty() {
+            modes.push(format!("[marked:{}]", self.marked.len()));
+        }
+        if let Some(preset) = &self.persistence.active_preset {
+            modes.push(format!("[preset:{}]", preset));
+        }
+        modes.join(" ")
+    }
+
+    /// What to show instead of an empty list, e.g. in a brand new session.
+    fn empty_hint(&self) -> Option<String> {
+        if self.panes.is_empty() {
+            let add_key = self
+                .config
+                .keymap
+                .bindings()
+                .iter()
+                .find(|(action, _)| *action == Action::Add)
+                .and_then(|(_, keys)| keys.first())
+                .map(format_key);
+            return Some(match add_key {
+                Some(key) => format!("No panes yet, press {} to add the focused pane", key),
+                None => "No panes yet".to_string(),
+            });
+        }
+        if self.visible_indices().is_empty() {
+            return Some("No matching panes".to_string());
+        }
+        None
+    }
+
+    /// The note of the selected bookmark, or the note being typed.
+    fn note_line(&self) -> Option<String> {
+        if let Some(input) = &self.note_input {
+            return Some(format!("Note: {}_", input));
+        }
+        let note = self.panes.get(self.selected)?.note.as_ref()?;
+        Some(self.paint(&format!("Note: {}", note), |s| s.italic().to_string()))
+    }
+
+    /// Number of lines shown above the list.
+    fn header_len(&self) -> usize {
+        let header = match &self.browse {
+            Some(_) => 1,
+            None => {
+                self.search.is_some() as usize
+                    + self.empty_hint().is_some() as usize
+                    + self.note_line().is_some() as usize
+            }
+        };
+        self.locked as usize
+            + self.last_error.is_some() as usize
+            + (self.persistence.pending_count() > 0) as usize
+            + self.adding.is_some() as usize
+            + self.message.is_some() as usize
+            + header
+    }
+
+    /// The first row and the number of rows of the list shown in `rows`, at most `max_rows`,
+    /// keeping the selection in view. `None` while the size isn't known yet.
+    fn scroll_window(&self, rows: usize) -> Option<(usize, usize)> {
+        if rows == 0 {
+            return None;
+        }
+        let footer_len = self.config.show_footer as usize;
+        let mut height = rows.saturating_sub(self.header_len() + footer_len).max(1);
+        if let Some(max_rows) = self.config.max_rows {
+            height = height.min(max_rows);
+        }
+        let (selected, len) = match &self.browse {
+            Some(browse) => (browse.selected, browse.panes.len()),
+            None => {
+                let visible = self.visible_indices();
+                let selected = visible.iter().position(|&idx| idx == self.selected);
+                let selected = selected.unwrap_or(0);
+                // Separator rows are rows of the list too, before the bookmark they precede.
+                let separators = self.separator_rows(&visible);
+                let before = separators.iter().filter(|&&row| row <= selected).count();
+                (selected + before, visible.len() + separators.len())
+            }
+        };
+        Some((scroll_offset(self.scroll, selected, len, height), height))
+    }
+
+    /// The rows of `visible` preceded by a separator row with `tab_separator row`: those in
+    /// another tab than the row before.
+    fn separator_rows(&self, visible: &[usize]) -> Vec<usize> {
+        if self.config.tab_separator != TabSeparator::Row {
+            return Vec::new();
+        }
+        (1..visible.len())
+            .filter(|&row| !self.same_tab(visible[row - 1], visible[row]))
+            .collect()
+    }
+
+    /// Whether the bookmarks at indices `a` and `b` are in the same tab.
+    fn same_tab(&self, a: usize, b: usize) -> bool {
+        self.panes[a].tab_info.name == self.panes[b].tab_info.name
+    }
+
+    /// The overview of the key bindings, as configured.
+    fn help_lines(&self) -> Vec<String> {
+        let header = "Keys (? or Esc to close)";
+        let mut lines = vec![self.paint(header, |s| s.dimmed().to_string())];
+        let bindings: Vec<(String, &str)> = self
+            .config
+            .keymap
+            .bindings()
+            .iter()
+            .filter(|(_, keys)| !keys.is_empty())
+            .map(|(action, keys)| {
+                let keys: Vec<String> = keys.iter().map(format_key).collect();
+                (keys.join(", "), action.description())
+            })
+            .collect();
+        let width = bindings
+            .iter()
+            .map(|(keys, _)| keys.chars().count())
+            .max()
+            .unwrap_or(0);
+        for (keys, description) in bindings {
+            let padding = " ".repeat(width - keys.chars().count());
+            lines.push(format!(
+                "{}{}  {}",
+                self.paint(&keys, |s| s.bold().to_string()),
+                padding,
+                description
+            ));
+        }
+        lines
+    }
+
+    /// The details of the selected bookmark, each wrapped to `cols` rather than cut, as a
+    /// command line or note can be long.
+    fn detail_lines(&self, cols: usize) -> Vec<String> {
+        let Some(pane) = self.panes.get(self.selected) else {
+            return Vec::new();
+        };
+        let mut fields = vec![
+            ("Tab", pane.tab_info.name.clone()),
+            ("Title", pane.pane_info.title.clone()),
+        ];
+        if let Some(command) = &pane.pane_info.terminal_command {
+            fields.push(("Command", command.clone()));
+        }
+        if let Some(note) = &pane.note {
+            fields.push(("Note", note.clone()));
+        }
+        fields.push(("Id", pane.pane_info.id.to_string()));
+        let mut lines = Vec::new();
+        for (name, value) in fields {
+            let text: Vec<char> = format!("{}: {}", name, value).chars().collect();
+            lines.extend(
+                text.chunks(cols.max(1))
+                    .map(|c| c.iter().collect::<String>()),
+            );
+        }
+        lines
+    }
+
+    /// Rows left for the details below their header.
+    fn details_height(rows: usize) -> usize {
+        rows.saturating_sub(1).max(1)
+    }
+
+    /// The details shown in `rows`: a header, then as many detail lines as fit from
+    /// `detail_scroll` on.
+    fn details_window(&self, rows: usize, cols: usize) -> Vec<String> {
+        let header = "Details (i or Esc to close, arrows to scroll)";
+        let mut lines = vec![self.paint(&truncate(header, cols), |s| s.dimmed().to_string())];
+        let height = Self::details_height(rows);
+        let details = self.detail_lines(cols);
+        let last = details.len().saturating_sub(height);
+        let offset = self.detail_scroll.min(last);
+        lines.extend(details.into_iter().skip(offset).take(height));
+        lines
+    }
+
+    /// Scroll the details by `delta` rows, stopping once the last one is shown.
+    fn scroll_details(&mut self, delta: isize) -> bool {
+        let len = self.detail_lines(self.cols).len();
+        let last = len.saturating_sub(Self::details_height(self.rows));
+        let scroll = (self.detail_scroll as isize + delta).clamp(0, last as isize) as usize;
+        let moved = scroll != self.detail_scroll;
+        self.detail_scroll = scroll;
+        moved
+    }
+
+    /// A single row is too small for the list, so it always uses the bar layout.
+    fn is_bar(&self, rows: usize) -> bool {
+        self.config.layout == Layout::Bar || rows == 1
+    }
+
+    /// The `bar` layout: the shown list on one line, numbered and truncated to `cols`. Leading
+    /// entries are dropped when needed to keep the selected one in view.
+    fn bar_line(&self, cols: usize) -> String {
+        if let Some(last_error) = &self.last_error {
+            let line: String = last_error.chars().take(cols).collect();
+            return self.paint(&line, |s| s.red().to_string());
+        }
+        let (prefix, entries): (String, Vec<(String, bool)>) = match &self.browse {
+            Some(browse) => (
+                "All panes: ".to_string(),
+                browse
+                    .panes
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, pane)| (pane.pane_info.title.clone(), idx == browse.selected))
+                    .collect(),
+            ),
+            None => (
+                match &self.search {
+                    Some(search) if search.editing => format!("/{}_ ", search.query),
+                    Some(search) => format!("/{} ", search.query),
+                    None => String::new(),
+                },
+                self.visible_indices()
+                    .into_iter()
+                    .map(|idx| {
+                        let pane = &self.panes[idx];
+                        let title = format!(
+                            "{}{}{}",
+                            self.status_prefix(pane),
+                            self.icon_prefix(pane),
+                            self.capped_title(pane)
+                        );
+                        (title, idx == self.selected)
+                    })
+                    .collect(),
+            ),
+        };
+        let entries: Vec<(String, bool)> = entries
+            .into_iter()
+            .enumerate()
+            .map(|(row, (title, selected))| {
+                let marker = if selected && !self.config.color {
+                    self.config.selection_marker.as_str()
+                } else {
+                    ""
+                };
+                (format!("{}{} {}", marker, row + 1, title), selected)
+            })
+            .collect();
+        let width = |entry: &(String, bool)| entry.0.chars().count() + BAR_DELIMITER.len();
+        let available = cols.saturating_sub(prefix.chars().count());
+        let mut start = 0;
+        if let Some(selected) = entries.iter().position(|(_, selected)| *selected) {
+            while start < selected
+                && entries[start..=selected].iter().map(width).sum::<usize>() > available
+            {
+                start += 1;
+            }
+        }
+
+        let mut line = prefix.chars().take(cols).collect::<String>();
+        let mut used = line.chars().count();
+        for (idx, (text, selected)) in entries.iter().enumerate().skip(start) {
+            let delimiter = if idx == start { "" } else { BAR_DELIMITER };
+            let room = cols.saturating_sub(used + delimiter.len());
+            if room == 0 {
+                break;
+            }
+            let text = truncate(text, room);
+            used += delimiter.len() + text.chars().count();
+            line.push_str(delimiter);
+            if *selected {
+                line.push_str(&self.paint_selected(&text));
+            } else {
+                line.push_str(&text);
+            }
+        }
+        line
+    }
+
+    fn browse_lines(&self, browse: &Browse, cols: usize) -> Vec<String> {
+        let width = cols.saturating_sub(self.selection_width());
+        browse
+            .panes
+            .iter()
+            .enumerate()
+            .map(|(idx, pane)| {
+                let mut line = pane.to_string();
+                line.push_str(&self.title_suffix(pane));
+                if self.config.show_ids {
+                    line.push_str(&format!(" #{}", pane.pane_info.id));
+                }
+                if self.is_bookmarked(pane.pane_info.id) {
+                    line.push_str(" (bookmarked)");
+                }
+                let line = truncate(&line, width);
+                let selected = idx == browse.selected;
+                let line = self.mark_selection(line, selected);
+                let line = if selected {
+                    self.paint_selected(&line)
+                } else {
+                    line
+                };
+                self.with_gutter(line, selected)
+            })
+            .collect()
+    }
+
+    fn bookmark_lines(&self, cols: usize) -> Vec<String> {
+        let width = cols.saturating_sub(self.selection_width());
+        let visible = self.visible_indices();
+        let separators = self.separator_rows(&visible);
+        visible
+            .iter()
+            .enumerate()
+            .flat_map(|(row, &idx)| {
+                let pane = &self.panes[idx];
+                let previous = row.checked_sub(1).map(|row| &self.panes[visible[row]]);
+                let icon = format!(
+                    "{}{}{}",
+                    self.mark_prefix(pane),
+                    self.status_prefix(pane),
+                    self.icon_prefix(pane)
+                );
+                let label = self.pane_label(pane, previous);
+                // A cut title ends in an ellipsis, past which search matches aren't shown. Matches
+                // are found in the title as it is, so none are shown in a reformatted path.
+                let label_len = label.chars().count();
+                let title = self.capped_title(pane);
+                let shown_len = if self.shown_title(pane) != pane.title() {
+                    label_len - title.chars().count()
+                } else if title != pane.title() {
+                    label_len - 1
+                } else {
+                    label_len
+                };
+                let mut line = format!("{}{}", icon, label);
+                line.push_str(&self.title_suffix(pane));
+                if self.config.show_ids {
+                    line.push_str(&format!(" #{}", pane.pane_info.id));
+                }
+                if let Some(tag) = &pane.tag {
+                    line.push(' ');
+                    line.push_str(tag);
+                }
+                if pane.base {
+                    line.push_str(" (base)");
+                }
+                if pane.ephemeral {
+                    line.push_str(" (not saved)");
+                }
+                if pane.pinned {
+                    line.push_str(" (pinned)");
+                }
+                if pane.closed {
+                    line.push_str(" (closed)");
+                }
+                // Like a capped title, a row cut to the width hides the matches past the cut.
+                let shown_len = if line.chars().count() > width {
+                    shown_len.min(width.saturating_sub(1 + icon.chars().count()))
+                } else {
+                    shown_len
+                };
+                let line = truncate(&line, width);
+                let selected = idx == self.selected;
+                let just_added = self.just_added.map(|(id, _)| id) == Some(pane.pane_info.id);
+                let line = self.mark_selection(line, selected);
+                let line = if selected && just_added {
+                    let line = self.paint_selected(&line);
+                    self.paint(&line, |s| s.underline().to_string())
+                } else if selected {
+                    self.paint_selected(&line)
+                } else if just_added {
+                    self.paint(&line, |s| s.green().to_string())
+                } else if pane.closed {
+                    self.paint(&line, |s| s.dimmed().to_string())
+                } else if let Some((_, positions)) = self
+                    .search
+                    .as_ref()
+                    .and_then(|s| s.score(pane, &self.config.tag_prefix))
+                {
+                    // Matches are positions in the label, which comes after the icon.
+                    let offset = icon.chars().count();
+                    let positions: Vec<usize> = positions
+                        .iter()
+                        .filter(|&&p| p < shown_len)
+                        .map(|p| p + offset)
+                        .collect();
+                    self.highlight_matches(&line, &positions)
+                } else {
+                    line
+                };
+                let ends_tab =
+                    (visible.get(row + 1)).is_some_and(|&next| !self.same_tab(idx, next));
+                let line = if self.config.tab_separator == TabSeparator::Inline && ends_tab {
+                    self.paint(&line, |s| s.underline().to_string())
+                } else {
+                    line
+                };
+                let line = self.with_gutter(line, selected);
+                if separators.contains(&row) {
+                    let separator = truncate(&format!("── {}", pane.tab_info.name), cols);
+                    vec![self.paint(&separator, |s| s.dimmed().to_string()), line]
+                } else {
+                    vec![line]
+                }
+            })
+            .collect()
+    }
+
+    /// Highlight the characters of `line` at `positions`, i.e. those matching the search.
+    fn highlight_matches(&self, line: &str, positions: &[usize]) -> String {
+        line.chars()
+            .enumerate()
+            .map(|(idx, c)| {
+                if positions.contains(&idx) {
+                    self.paint(&c.to_string(), |s| s.yellow().bold().to_string())
+                } else {
+                    c.to_string()
+                }
+            })
+            .collect()
+    }
+
+    /// Count a miss of the bookmarks still pending after a `PaneUpdate`, dropping those pending
+    /// for `prune_pending_after` of them. Other updates of the list don't count, as they can
+    /// come in bursts, e.g. while the list is restored.
+    fn prune_pending(&mut self) {
+        self.persistence.count_misses();
+        if let Some(max_misses) = self.config.prune_pending_after {
+            let pruned = self.persistence.prune_pending(max_misses);
+            if pruned > 0 {
+                self.message = Some(format!(
+                    "Dropped {} saved panes that didn't show up",
+                    pruned
+                ));
+                self.save();
+            }
+        }
+    }
+
+    /// Update panes updates the pane states based on the latest pane_manifest and tab_info
+    fn update_panes(&mut self) -> Option<()> {
+        let selected_key = self.selected_key();
+
+        // Until both a `PaneUpdate` and a `TabUpdate` arrived, update what the one we have allows.
+        let (pane_manifest, tab_info) = match (self.pane_manifest.clone(), self.tab_info.clone()) {
+            (Some(pane_manifest), Some(tab_info)) => (pane_manifest, tab_info),
+            (Some(pane_manifest), None) => {
+                let keep_closed = self.config.on_pane_close == OnPaneClose::Keep;
+                refresh_pane_infos(
+                    &mut self.panes,
+                    &pane_manifest,
+                    &self.pane_index,
+                    keep_closed,
+                );
+                self.clamp_selection();
+                return Some(());
+            }
+            (None, Some(tab_info)) => {
+                refresh_tab_infos(&mut self.panes, &tab_info);
+                return Some(());
+            }
+            (None, None) => return None,
+        };
+
+        // Update panes to filter our invalid panes (e.g. tab/pane was closed).
+        let keep_closed = self.config.on_pane_close == OnPaneClose::Keep;
+        let panes = get_valid_panes(
+            &self.panes.clone(),
+            &pane_manifest,
+            &self.pane_index,
+            &tab_info,
+            keep_closed,
+        );
+        self.panes = panes;
+        rematch_closed_panes(&mut self.panes, &pane_manifest, &tab_info);
+        if self.config.dedup_by_pane_id {
+            let mut seen = HashSet::new();
+            self.panes.retain(|p| seen.insert(p.pane_info.id));
+            self.clamp_selection();
+        }
+
+        // Restore bookmarks loaded from disk whose panes have shown up.
+        let restored =
+            self.persistence
+                .match_pending_bookmarks(&self.panes, &pane_manifest, &tab_info);
+        if !restored.is_empty() {
+            for pane in &restored {
+                self.persistence.forget_kept_delete(pane.key());
+            }
+            self.panes.extend(restored);
+            self.sort_panes();
+        }
+        // Keep the browse list in sync with the session
+        if let Some(browse) = &mut self.browse {
+            browse.panes = get_all_panes(&pane_manifest, &tab_info);
+            if browse.selected >= browse.panes.len() {
+                browse.selected = browse.panes.len().saturating_sub(1);
+            }
+        }
+
+        // While searching, stay on the selected bookmark rather than jumping around the results
+        if self.search.is_some() && !selected_key.is_some_and(|key| self.select_key(key)) {
+            self.clamp_selection();
+            self.update_search_selection();
+        }
+
+        // Update currently focused pane
+        let focused_closed = self.focused_pane.as_ref().is_some_and(|focused| {
+            !pane_manifest
+                .panes
+                .values()
+                .flatten()
+                .any(|p| !p.is_plugin && p.id == focused.pane_info.id)
+        });
+        if focused_closed {
+            self.focused_pane = None;
+        }
+        let previous_focus = self.focused_pane.as_ref().map(|p| p.pane_info.id);
+        let tab_info = get_focused_tab(&tab_info)?;
+        let pane_info = get_focused_pane(tab_info.position, &pane_manifest)?;
+        if previous_focus != Some(pane_info.id) {
+            self.focus_count += 1;
+            self.last_focused.insert(pane_info.id, self.focus_count);
+        }
+        // Always the latest focus, so adding while the plugin is open adds the pane focused last.
+        self.focused_pane = Some(Pane::new(pane_info, tab_info));
+
+        if self.search.is_some() || self.config.initial_selection != InitialSelection::Focused {
+            return Some(());
+        }
+
+        // Follow the focus to another pane, but otherwise stay on the selected bookmark, so
+        // updates of unrelated panes don't undo moving the selection.
+        let focus_changed = previous_focus != self.focused_pane.as_ref().map(|p| p.pane_info.id);
+        if focus_changed {
+            self.select_focused();
+        } else if let Some(key) = selected_key {
+            self.select_key(key);
+        }
+
+        Some(())
+    }
+}
+
+register_plugin!(State);
+
+impl ZellijPlugin for State {
+    fn load(&mut self, configuration: BTreeMap<String, String>) {
+        let mut errors = Vec::new();
+        self.config = Config::from_configuration(&configuration, &mut errors);
+        if !errors.is_empty() {
+            self.last_error = Some(errors.join("; "));
+        }
+        debug_log!(
+            self.config.debug,
+            "config parsed (persist_format: {:?}, errors: {:?})",
+            self.config.persist_format,
+            errors
+        );
+        self.persistence.format = self.config.persist_format;
+        self.persistence.debug = self.config.debug;
+        self.persistence.enabled = self.config.persistence;
+        self.persistence.record_geometry = self.config.record_geometry;
+        self.persistence.base_list_path = self.config.base_list_path.clone();
+        self.locked = self.config.locked;
+        self.configured = ViewPreferences {
+            sort_mode: Some(self.config.sort_mode),
+            collapse_tab_labels: Some(self.config.collapse_tab_labels),
+            scroll: None,
+        };
+        self.configured_show_ids = self.config.show_ids;
+        request_permission(&self.config.permissions());
+        subscribe(&[
+            EventType::Key,
+            EventType::TabUpdate,
+            EventType::PaneUpdate,
+            EventType::ModeUpdate,
+            EventType::PermissionRequestResult,
+            EventType::RunCommandResult,
+            EventType::Timer,
+            EventType::Visible,
+        ]);
+        self.restart_save_interval();
+        if self.config.start_hidden {
+            // Events keep arriving while hidden, so bookmarks are still loaded in the background.
+            hide_self();
+        }
+    }
+
+    fn update(&mut self, event: Event) -> bool {
+        let mut should_render = false;
+        self.hid = false;
+        if let Event::Key(_) = event {
+            should_render = self.message.take().is_some() | self.last_error.take().is_some();
+            self.restart_auto_hide();
+        }
+        match event {
+            Event::TabUpdate(tab_info) => {
+                self.tab_info = Some(tab_info);
+                self.update_panes();
+                should_render = true;
+            }
+            Event::PaneUpdate(mut pane_manifest) => {
+                // The mark of a preview isn't the pane's title, so it stays out of the list and
+                // the saved bookmarks.
+                if let Some(preview) = &self.preview {
+                    (pane_manifest.panes.values_mut().flatten())
+                        .filter(|p| p.id == preview.pane_id && !p.is_plugin)
+                        .for_each(|p| p.title = preview.title.clone());
+                }
+                self.pane_index = index_panes(&pane_manifest);
+                self.pane_manifest = Some(pane_manifest);
+                self.update_panes();
+                // Bookmarks are only matched once the tabs are known too.
+                if self.tab_info.is_some() {
+                    self.prune_pending();
+                }
+                self.expand_stacked();
+                should_render = true;
+            }
+            Event::ModeUpdate(mode_info)
+                if mode_info.session_name != self.persistence.session_name =>
+            {
+                let lost =
+                    self.persistence.session_name.is_some() && mode_info.session_name.is_none();
+                self.persistence.set_session_name(mode_info.session_name);
+                // The bookmark file is named after the session, so saves wait for the name to
+                // come back. Unsaved changes stay marked dirty until then.
+                if lost && self.config.persistence {
+                    self.persistence_paused = true;
+                    self.last_error =
+                        Some("Session name unavailable; persistence paused".to_string());
+                    should_render = true;
+                } else if std::mem::take(&mut self.persistence_paused) {
+                    self.last_error = None;
+                    self.message = Some("Persistence resumed".to_string());
+                    self.flush();
+                    should_render = true;
+                }
+            }
+            Event::PermissionRequestResult(PermissionStatus::Granted) => {
+                self.persistence.set_permission_granted();
+            }
+            Event::RunCommandResult(exit_code, stdout, _stderr, context) => {
+                if Persistence::is_load_result(&context) {
+                    if let Some(notice) = self.persistence.on_load_command(exit_code, &stdout) {
+                        self.last_error = Some(notice);
+                    }
+                    self.apply_view_preferences();
+                    self.update_panes();
+                    // Save right away so the corrupt file is moved aside.
+                    self.dirty |= self.persistence.backup_pending();
+                    self.flush();
+                    should_render = true;
+                } else if Persistence::is_reload_result(&context) {
+                    match self
+                        .persistence
+                        .on_reload_command(exit_code, &stdout, &self.panes)
+                    {
+                        Ok(new) => {
+                            self.update_panes();
+                            self.save();
+                            self.message = Some(format!("Reloaded from disk ({} new)", new));
+                        }
+                        Err(e) => self.last_error = Some(format!("Could not reload: {}", e)),
+                    }
+                    should_render = true;
+                } else if Persistence::is_base_load_result(&context) {
+                    if let Some(notice) =
+                        self.persistence
+                            .on_base_load_command(exit_code, &stdout, &self.panes)
+                    {
+                        self.last_error = Some(notice);
+                    }
+                    self.update_panes();
+                    should_render = true;
+                } else if let Some(what) = compat::clipboard_result(&context) {
+                    if exit_code == Some(0) {
+                        self.message = Some(format!("Copied {}", what));
+                    } else {
+                        self.last_error = Some("Clipboard unavailable".to_string());
+                    }
+                    should_render = true;
+                } else if Persistence::is_export_result(&context) {
+                    if exit_code == Some(0) {
+                        self.message = Some("Layout exported".to_string());
+                    } else {
+                        self.last_error = Some("Could not export the layout".to_string());
+                    }
+                    should_render = true;
+                } else if Persistence::is_preset_save_result(&context) {
+                    if exit_code == Some(0) {
+                        self.message = Some("Preset saved".to_string());
+                    } else {
+                        self.last_error = Some("Could not save the preset".to_string());
+                    }
+                    should_render = true;
+                } else if Persistence::is_preset_load_result(&context) {
+                    match (self.persistence).on_load_preset_command(exit_code, &stdout, &context) {
+                        Ok((name, bookmarks)) if self.ensure_unlocked() => {
+                            self.replace_list(bookmarks, Some(name))
+                        }
+                        Ok(_) => {}
+                        Err(e) => self.last_error = Some(format!("Could not load: {}", e)),
+                    }
+                    should_render = true;
+                }
+            }
+            Event::Timer(_) => {
+                self.flush();
+                self.restart_save_interval();
+                self.check_auto_hide();
+                // Timer events don't tell which timeout fired, so a jump only ends once its own
+                // timeout has passed, like `check_auto_hide`.
+                let jump_timeout = Duration::from_secs_f64(JUMP_TIMEOUT_SECS);
+                if (self.awaiting_jump).is_some_and(|pressed| pressed.elapsed() >= jump_timeout) {
+                    self.awaiting_jump = None;
+                    self.message = None;
+                    should_render = true;
+                }
+                let preview_secs = Duration::from_secs_f64(PREVIEW_SECS);
+                if (self.preview.as_ref()).is_some_and(|p| p.at.elapsed() >= preview_secs) {
+                    self.end_preview();
+                }
+                if (self.just_added).is_some_and(|(_, until)| Instant::now() >= until) {
+                    self.just_added = None;
+                    should_render = true;
+                }
+            }
+            Event::Visible(true) => {
+                self.restart_auto_hide();
+                if self.search.is_none() {
+                    match self.config.initial_selection.index(self.panes.len()) {
+                        // The position in the list as shown, see `visible_indices`.
+                        Some(position) => {
+                            self.selected =
+                                self.visible_indices().get(position).copied().unwrap_or(0)
+                        }
+                        None => {
+                            self.select_focused();
+                        }
+                    }
+                    should_render = true;
+                }
+            }
+            Event::Visible(false) => {
+                self.last_activity = None;
+                self.flush();
+            }
+            Event::Key(key) if self.note_input.is_some() => {
+                should_render |= self.handle_note_key(key);
+            }
+            Event::Key(key) if self.search.as_ref().is_some_and(|s| s.editing) => {
+                should_render |= self.handle_search_key(key);
+            }
+            Event::Key(key) => {
+                let render = self.handle_key(key);
+                debug_assert!(
+                    !(render && self.hid),
+                    "{:?} hid the plugin and requested a render",
+                    key
+                );
+                should_render |= render;
+            }
+            _ => (),
+        };
+
+        should_render
+    }
+
+    fn pipe(&mut self, pipe_message: PipeMessage) -> bool {
+        self.handle_pipe_message(&pipe_message)
+    }
+
+    fn render(&mut self, rows: usize, cols: usize) {
+        self.rows = rows;
+        self.cols = cols;
+        // Render is also called when the plugin is resized, so the window follows right away.
+        // The offset is saved with the list, so a change of it is a change to save.
+        if let Some((offset, _)) = self.scroll_window(rows) {
+            if offset != self.scroll {
+                self.scroll = offset;
+                self.save();
+            }
+        }
+        println!("{}", self.render_to_string(rows, cols));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pane(id: u32, title: &str) -> Pane {
+        let pane_info = PaneInfo {
+            id,
+            title: title.to_string(),
+            ..Default::default()
+        };
+        let tab_info = TabInfo {
+            name: "Tab #1".to_string(),
+            ..Default::default()
+        };
+        Pane::new(pane_info, tab_info)
+    }
+
+    fn state_with(titles: &[&str]) -> State {
+        State {
+            panes: (0..).zip(titles).map(|(id, t)| pane(id, t)).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn titles(state: &State) -> Vec<&str> {
+        state
+            .panes
+            .iter()
+            .map(|p| p.pane_info.title.as_str())
+            .collect()
+    }
+
+    /// `text` without its ANSI color codes.
+    fn plain(text: &str) -> String {
+        let mut plain = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|&c| c == 'm');
+            } else {
+                plain.push(c);
+            }
+        }
+        plain
+    }
+
+    #[test]
+    fn delete_last_selects_new_last() {
+        let mut state = state_with(&["a", "b", "c"]);
+        state.selected = 2;
+        state.handle_action(Action::Delete);
+        assert_eq!(titles(&state), ["a", "b"]);
+        assert_eq!(state.selected, 1);
+    }
+
+    #[test]
+    fn delete_middle_selects_row_above() {
+        let mut state = state_with(&["a", "b", "c"]);
+        state.selected = 1;
+        state.handle_action(Action::Delete);
+        assert_eq!(titles(&state), ["a", "c"]);
+        assert_eq!(state.selected, 0);
+    }
+
+    #[test]
+    fn delete_first_selects_new_first() {
+        let mut state = state_with(&["a", "b", "c"]);
+        state.handle_action(Action::Delete);
+        assert_eq!(titles(&state), ["b", "c"]);
+        assert_eq!(state.selected, 0);
+    }
+
+    #[test]
+    fn delete_only_empties_list() {
+        let mut state = state_with(&["a"]);
+        state.handle_action(Action::Delete);
+        assert!(state.panes.is_empty());
+        assert_eq!(state.selected, 0);
+    }
+
+    #[test]
+    fn unrelated_timer_keeps_jump_waiting() {
+        let mut state = state_with(&["a", "b"]);
+        state.config.jump_leader = Some(Key::Char(' '));
+        state.handle_key(Key::Char(' '));
+        state.update(Event::Timer(0.5));
+        assert!(state.awaiting_jump.is_some());
+        state.handle_key(Key::Char('2'));
+        assert_eq!(state.selected, 1);
+    }
+
+    #[test]
+    fn unrelated_timer_keeps_added_highlight() {
+        let mut state = state_with(&["a"]);
+        state.config.hide_after_add = false;
+        state.highlight_added(0);
+        state.update(Event::Timer(0.5));
+        assert!(state.just_added.is_some());
+    }
+
+    #[test]
+    fn initial_selection_stays_in_list() {
+        let index = |selection: InitialSelection| -> Vec<Option<usize>> {
+            (0..=4).map(|len| selection.index(len)).collect()
+        };
+        assert_eq!(index(InitialSelection::Focused), [None; 5]);
+        assert_eq!(index(InitialSelection::Top), [Some(0); 5]);
+        assert_eq!(
+            index(InitialSelection::Middle),
+            [Some(0), Some(0), Some(0), Some(1), Some(1)]
+        );
+    }
+
+    #[test]
+    fn cycle_sort_wraps_around() {
+        let mut state = state_with(&["a"]);
+        let mut modes = Vec::new();
+        for _ in 0..5 {
+            state.handle_action(Action::CycleSort);
+            modes.push(state.config.sort_mode);
+        }
+        let expected = [
+            SortMode::Recent,
+            SortMode::Alpha,
+            SortMode::Manual,
+            SortMode::Tab,
+            SortMode::Recent,
+        ];
+        assert_eq!(modes, expected);
+    }
+
+    #[test]
+    fn cycling_back_to_manual_keeps_manual_order() {
+        let mut state = state_with(&["c", "a", "b"]);
+        state.config.sort_mode = SortMode::Manual;
+        state.handle_action(Action::CycleSort);
+        state.handle_action(Action::CycleSort);
+        state.handle_action(Action::CycleSort);
+        assert_eq!(state.config.sort_mode, SortMode::Alpha);
+        assert_eq!(state.visible_indices(), [1, 2, 0]);
+        state.handle_action(Action::CycleSort);
+        assert_eq!(state.config.sort_mode, SortMode::Manual);
+        assert_eq!(state.visible_indices(), [0, 1, 2]);
+        assert_eq!(titles(&state), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn duplicate_inserts_and_selects_copy() {
+        let mut state = state_with(&["a", "b"]);
+        state.handle_action(Action::Duplicate);
+        assert_eq!(titles(&state), ["a", "a", "b"]);
+        assert_eq!(state.selected, 1);
+        assert_eq!(state.panes[1].key(), (0, 1));
+    }
+
+    #[test]
+    fn copy_stays_selected_across_pane_updates() {
+        let mut state = state_with(&["a", "b"]);
+        let mut panes = vec![
+            state.panes[0].pane_info.clone(),
+            state.panes[1].pane_info.clone(),
+        ];
+        panes[0].is_focused = true;
+        let tab_info = TabInfo {
+            active: true,
+            ..state.panes[0].tab_info.clone()
+        };
+        let pane_manifest = PaneManifest {
+            panes: HashMap::from([(0, panes)]),
+        };
+        state.update(Event::TabUpdate(vec![tab_info]));
+        state.update(Event::PaneUpdate(pane_manifest.clone()));
+        state.handle_action(Action::Duplicate);
+        state.update(Event::PaneUpdate(pane_manifest));
+        assert_eq!(state.selected_key(), Some((0, 1)));
+    }
+
+    #[test]
+    fn marking_copy_leaves_original() {
+        let mut state = state_with(&["a"]);
+        state.handle_action(Action::Duplicate);
+        state.handle_action(Action::Mark);
+        state.handle_action(Action::DeleteMarked);
+        assert_eq!(state.panes.len(), 1);
+        assert_eq!(state.panes[0].key(), (0, 0));
+    }
+
+    #[test]
+    fn select_pipe_counts_rows_as_shown() {
+        let mut state = state_with(&["a", "b", "c"]);
+        state.config.reverse = true;
+        let pipe_message = PipeMessage {
+            source: PipeSource::Cli("test".to_string()),
+            name: SELECT_PIPE.to_string(),
+            payload: Some("1".to_string()),
+            args: BTreeMap::new(),
+            is_private: false,
+        };
+        state.handle_pipe_message(&pipe_message);
+        assert_eq!(state.selected, 2);
+    }
+
+    #[test]
+    fn locked_list_refuses_sort_and_reset() {
+        let mut state = state_with(&["a"]);
+        state.locked = true;
+        state.handle_action(Action::CycleSort);
+        assert_eq!(state.config.sort_mode, SortMode::Tab);
+        let pipe_message = PipeMessage {
+            source: PipeSource::Cli("test".to_string()),
+            name: RESET_CONFIG_PIPE.to_string(),
+            payload: None,
+            args: BTreeMap::new(),
+            is_private: false,
+        };
+        state.handle_pipe_message(&pipe_message);
+        assert!(state.locked);
+        assert_eq!(state.message.as_deref(), Some("The list is locked"));
+    }
+
+    #[test]
+    fn clearing_search_keeps_selected_bookmark() {
+        let mut state = state_with(&["alpha", "beta", "gamma", "beast"]);
+        state.handle_action(Action::Search);
+        state.handle_search_key(Key::Char('b'));
+        state.handle_search_key(Key::Char('e'));
+        assert_eq!(state.visible_indices(), [1, 3]);
+        assert_eq!(state.selected, 1);
+        state.handle_search_key(Key::Down);
+        state.handle_search_key(Key::Esc);
+        assert!(state.search.is_none());
+        assert_eq!(state.panes[state.selected].pane_info.title, "beast");
+    }
+
+    #[test]
+    fn search_without_matches_leaves_bookmarks_alone() {
+        let mut state = state_with(&["alpha", "beta"]);
+        state.handle_action(Action::Search);
+        state.handle_search_key(Key::Char('z'));
+        state.handle_search_key(Key::Char('\n'));
+        assert!(state.visible_indices().is_empty());
+        for action in [Action::Delete, Action::Pin, Action::Duplicate, Action::Mark] {
+            assert!(!state.handle_action(action));
+        }
+        assert_eq!(titles(&state), ["alpha", "beta"]);
+        assert!(state.panes.iter().all(|p| !p.pinned));
+        assert!(state.marked.is_empty());
+    }
+
+    #[test]
+    fn plugin_panes_are_never_added() {
+        let mut state = state_with(&[]);
+        let plugin = PaneInfo {
+            id: 1,
+            title: "harpoon".to_string(),
+            is_plugin: true,
+            ..Default::default()
+        };
+        assert!(!state.add_pane(Pane::new(plugin.clone(), TabInfo::default())));
+        let terminal = PaneInfo {
+            id: 1,
+            title: "zsh".to_string(),
+            is_focused: true,
+            ..Default::default()
+        };
+        let tab_info = TabInfo {
+            name: "Tab #1".to_string(),
+            active: true,
+            ..Default::default()
+        };
+        state.update(Event::TabUpdate(vec![tab_info]));
+        state.update(Event::PaneUpdate(PaneManifest {
+            panes: HashMap::from([(0, vec![plugin, terminal])]),
+        }));
+        state.handle_action(Action::AddAll);
+        assert_eq!(titles(&state), ["zsh"]);
+        assert!(!state.panes[0].pane_info.is_plugin);
+    }
+
+    #[test]
+    fn delete_marks_dirty_only_when_it_persists() {
+        let mut state = state_with(&["a", "b"]);
+        state.handle_action(Action::Delete);
+        assert!(state.dirty);
+        assert!(state.persistence.kept_deletes.is_empty());
+
+        let mut state = state_with(&["a", "b"]);
+        state.config.delete_persists = false;
+        state.handle_action(Action::Delete);
+        assert!(!state.dirty);
+        assert_eq!(state.persistence.kept_deletes.len(), 1);
+    }
+
+    #[test]
+    fn readding_pane_drops_its_kept_delete() {
+        let mut state = state_with(&["a", "b"]);
+        state.config.delete_persists = false;
+        let pane = state.panes[0].clone();
+        state.handle_action(Action::Delete);
+        assert!(state.add_pane(pane));
+        assert!(state.persistence.kept_deletes.is_empty());
+        assert_eq!(titles(&state), ["b", "a"]);
+    }
+
+    #[test]
+    fn compact_follows_delete_persists() {
+        let mut state = state_with(&["a", "b"]);
+        state.config.delete_persists = false;
+        state.pane_manifest = Some(PaneManifest {
+            panes: HashMap::from([(0, vec![state.panes[0].pane_info.clone()])]),
+        });
+        state.handle_action(Action::Compact);
+        assert_eq!(titles(&state), ["a"]);
+        assert!(!state.dirty);
+        assert_eq!(state.persistence.kept_deletes[0].0, (1, 0));
+    }
+
+    #[test]
+    fn type_ahead_takes_h_as_typed() {
+        let mut state = state_with(&["zsh", "htop"]);
+        state.handle_action(Action::TypeAhead);
+        state.handle_key(Key::Char('h'));
+        assert_eq!(state.selected, 1);
+        assert!(!state.hid);
+    }
+
+    #[test]
+    fn h_closes_help_then_hides() {
+        let mut state = state_with(&["zsh"]);
+        state.handle_action(Action::Help);
+        assert!(state.handle_key(Key::Char('h')));
+        assert!(!state.help);
+        assert!(!state.hid);
+        state.handle_key(Key::Char('h'));
+        assert!(state.hid);
+    }
+
+    #[test]
+    fn persistence_pauses_while_session_name_is_lost() {
+        let mut state = state_with(&["a"]);
+        let mode_update = |session_name: Option<&str>| {
+            Event::ModeUpdate(ModeInfo {
+                session_name: session_name.map(str::to_string),
+                ..Default::default()
+            })
+        };
+        state.update(mode_update(Some("work")));
+        assert!(state.last_error.is_none());
+        assert!(state.update(mode_update(None)));
+        assert!(state.persistence_paused);
+        assert_eq!(
+            state.last_error.as_deref(),
+            Some("Session name unavailable; persistence paused")
+        );
+        assert!(state.update(mode_update(Some("work"))));
+        assert!(!state.persistence_paused);
+        assert!(state.last_error.is_none());
+        assert_eq!(state.message.as_deref(), Some("Persistence resumed"));
+    }
+
+    #[test]
+    fn only_settings_changed_at_runtime_are_saved() {
+        let mut state = state_with(&["a"]);
+        state.load(BTreeMap::from([(
+            "sort_mode".to_string(),
+            "manual".to_string(),
+        )]));
+        let view = state.view_preferences();
+        assert_eq!(view.sort_mode, None);
+        assert_eq!(view.collapse_tab_labels, None);
+        state.handle_action(Action::CycleSort);
+        assert_eq!(state.view_preferences().sort_mode, Some(SortMode::Tab));
+    }
+
+    #[test]
+    fn ids_are_shown_until_reset() {
+        let mut state = state_with(&["nvim"]);
+        state.config.color = false;
+        assert!(!state.render_to_string(10, 80).contains("#0"));
+        state.config.show_ids = true;
+        assert!(state.render_to_string(10, 80).contains("nvim #0"));
+        state.reset_config();
+        assert!(!state.render_to_string(10, 80).contains("#0"));
+    }
+
+    #[test]
+    fn rows_are_cut_to_the_width() {
+        let mut state = state_with(&["a long title", "b"]);
+        state.config.color = false;
+        state.config.show_ids = true;
+        state.config.show_footer = false;
+        let out = state.render_to_string(10, 12);
+        assert!(out.lines().all(|line| line.chars().count() <= 12));
+        assert!(out.contains('…'));
+    }
+
+    #[test]
+    fn step_selection_wraps_or_clamps() {
+        assert_eq!(step_selection(0, 1, 0, true), 0);
+        assert_eq!(step_selection(0, -1, 0, false), 0);
+        assert_eq!(step_selection(0, 1, 1, true), 0);
+        assert_eq!(step_selection(0, -1, 1, false), 0);
+        assert_eq!(step_selection(2, 1, 3, true), 0);
+        assert_eq!(step_selection(0, -1, 3, true), 2);
+        assert_eq!(step_selection(2, 1, 3, false), 2);
+        assert_eq!(step_selection(0, -1, 3, false), 0);
+        assert_eq!(step_selection(1, 1, 3, false), 2);
+    }
+
+    #[test]
+    fn move_selection_wraps_unless_disabled() {
+        let mut state = state_with(&[]);
+        state.move_selection(1);
+        assert_eq!(state.selected, 0);
+
+        let mut state = state_with(&["a"]);
+        state.move_selection(-1);
+        assert_eq!(state.selected, 0);
+
+        let mut state = state_with(&["a", "b", "c"]);
+        state.move_selection(-1);
+        assert_eq!(state.selected, 2);
+        state.move_selection(1);
+        assert_eq!(state.selected, 0);
+        state.config.wrap_navigation = false;
+        state.move_selection(-1);
+        assert_eq!(state.selected, 0);
+        state.selected = 2;
+        state.move_selection(1);
+        assert_eq!(state.selected, 2);
+    }
+
+    #[test]
+    fn hiding_saves_unsaved_changes_once() {
+        let mut state = state_with(&["a"]);
+        state.persistence.enabled = true;
+        state.persistence.set_session_name(Some("main".to_string()));
+        state.persistence.on_load_command(Some(1), b"");
+        state.dirty = true;
+        state.hide();
+        assert!(!state.dirty);
+        assert!(!state.flush());
+    }
+
+    #[test]
+    fn expired_added_highlight_is_cleared() {
+        let mut state = state_with(&["a"]);
+        state.config.hide_after_add = false;
+        state.highlight_added(0);
+        state.just_added = state.just_added.map(|(id, _)| (id, Instant::now()));
+        assert!(state.update(Event::Timer(1.0)));
+        assert!(state.just_added.is_none());
+    }
+
+    #[test]
+    fn patterns_match_globs_or_substrings() {
+        assert!(matches_pattern("cargo watch", "watch"));
+        assert!(!matches_pattern("cargo watch", "test"));
+        assert!(matches_pattern("nvim src", "nvim*"));
+        assert!(!matches_pattern("lazygit", "nvim*"));
+        assert!(matches_pattern("cargo watch", "*watch"));
+        assert!(!matches_pattern("watch logs", "*watch"));
+        assert!(matches_pattern("cargo test --all", "cargo*all"));
+        assert!(matches_pattern("a b c", "a*b*c"));
+        assert!(!matches_pattern("a c b", "a*b*c"));
+        assert!(matches_pattern("anything", "*"));
+        assert!(matches_pattern("aa", "a*a"));
+        assert!(!matches_pattern("a", "a*a"));
+        assert!(!matches_pattern("ab", "ab*b"));
+    }
+
+    #[test]
+    fn replace_waits_for_confirmation() {
+        let imported = vec![PaneBookmark::from(&pane(9, "c"))];
+        let mut state = state_with(&["a", "b"]);
+        state.replace_list(imported.clone(), None);
+        assert!(state.confirm_replace.is_some());
+        assert!(state.handle_key(Key::Char('n')));
+        assert!(state.confirm_replace.is_none());
+        assert_eq!(titles(&state), ["a", "b"]);
+        assert!(state.persistence.pending_bookmarks.is_empty());
+
+        state.replace_list(imported, None);
+        assert_eq!(titles(&state), ["a", "b"]);
+        assert!(state.handle_key(Key::Char('y')));
+        assert!(state.panes.is_empty());
+        assert_eq!(state.persistence.pending_bookmarks.len(), 1);
+    }
+
+    #[test]
+    fn loaded_preset_replaces_once_confirmed() {
+        let context = BTreeMap::from([
+            (
+                persistence::CONTEXT_KEY.to_string(),
+                "preset_load".to_string(),
+            ),
+            ("preset".to_string(), "web".to_string()),
+        ]);
+        let stdout = br#"{"bookmarks": [{"tab_name": "Tab #1", "pane_title": "c"}]}"#;
+        for (key, kept) in [(Key::Char('n'), vec!["a", "b"]), (Key::Char('y'), vec![])] {
+            let mut state = state_with(&["a", "b"]);
+            let result = Event::RunCommandResult(Some(0), stdout.to_vec(), vec![], context.clone());
+            state.update(result);
+            assert!(state.confirm_replace.is_some());
+            assert_eq!(titles(&state), ["a", "b"]);
+            state.handle_key(key);
+            assert_eq!(titles(&state), kept);
+        }
+    }
+
+    #[test]
+    fn copy_takes_the_command_else_the_title() {
+        let mut command_pane = pane(0, "watch");
+        command_pane.pane_info.terminal_command = Some("cargo watch".to_string());
+        assert_eq!(clipboard_text(&command_pane), ("cargo watch", "command"));
+        assert_eq!(clipboard_text(&pane(1, "zsh")), ("zsh", "title"));
+    }
+
+    #[test]
+    fn copy_reports_its_result() {
+        let mut state = state_with(&["zsh"]);
+        state.persistence.enabled = false;
+        state.handle_action(Action::CopyCommand);
+        assert!(state.last_error.as_ref().unwrap().contains("persistence"));
+
+        let context = BTreeMap::from([
+            (
+                persistence::CONTEXT_KEY.to_string(),
+                "clipboard".to_string(),
+            ),
+            ("copied".to_string(), "title".to_string()),
+        ]);
+        let result = |code| Event::RunCommandResult(Some(code), vec![], vec![], context.clone());
+        let mut state = state_with(&["zsh"]);
+        assert!(state.update(result(0)));
+        assert_eq!(state.message.as_deref(), Some("Copied title"));
+        assert!(state.update(result(1)));
+        assert_eq!(state.last_error.as_deref(), Some("Clipboard unavailable"));
+    }
+
+    #[test]
+    fn only_pane_updates_count_towards_pruning() {
+        let mut state = state_with(&[]);
+        state.config.prune_pending_after = Some(2);
+        state.persistence.pending_bookmarks = vec![PaneBookmark::from(&pane(9, "gone"))];
+        state.update(Event::TabUpdate(vec![TabInfo::default()]));
+        state.update(Event::PaneUpdate(PaneManifest::default()));
+        for _ in 0..3 {
+            state.update(Event::TabUpdate(vec![TabInfo::default()]));
+        }
+        assert_eq!(state.persistence.pending_bookmarks.len(), 1);
+        state.update(Event::PaneUpdate(PaneManifest::default()));
+        assert!(state.persistence.pending_bookmarks.is_empty());
+    }
+
+    #[test]
+    fn scrolling_is_saved_and_kept_when_shown() {
+        let titles: Vec<String> = (0..30).map(|i| format!("pane {}", i)).collect();
+        let mut state = state_with(&titles.iter().map(String::as_str).collect::<Vec<_>>());
+        state.config.show_footer = false;
+        state.render(10, 80);
+        assert!(!state.dirty);
+        state.selected = 20;
+        state.render(10, 80);
+        assert!(state.scroll > 0);
+        assert!(state.dirty);
+
+        // Showing the plugin again keeps the offset while the selection is in view.
+        let scroll = state.scroll;
+        state.dirty = false;
+        state.update(Event::Visible(true));
+        state.render(10, 80);
+        assert_eq!(state.scroll, scroll);
+        assert!(!state.dirty);
+    }
+
+    #[test]
+    fn stacked_pane_is_focused_again_while_collapsed() {
+        let mut state = state_with(&["a", "b"]);
+        state.panes[1].pane_info.pane_rows = 1;
+        state.selected = 1;
+        state.handle_action(Action::Focus);
+        assert_eq!(state.expanding, Some(1));
+        let collapsed = state.panes[1].pane_info.clone();
+        state.pane_manifest = Some(PaneManifest {
+            panes: HashMap::from([(0, vec![collapsed])]),
+        });
+        assert!(state.expand_stacked());
+        assert!(!state.expand_stacked());
+
+        // A pane that isn't stacked is focused once.
+        state.selected = 0;
+        state.handle_action(Action::Focus);
+        assert_eq!(state.expanding, None);
+    }
+
+    #[test]
+    fn long_details_scroll_within_the_window() {
+        let mut state = state_with(&["cargo"]);
+        state.config.color = false;
+        state.panes[0].pane_info.terminal_command = Some("cargo watch -x test".repeat(3));
+        state.panes[0].note = Some("runs the tests".to_string());
+        (state.rows, state.cols) = (4, 20);
+        state.handle_key(Key::Char('i'));
+        let lines = state.detail_lines(20);
+        assert!(lines.len() > 3);
+        assert!(lines.iter().all(|line| line.chars().count() <= 20));
+        let shown = state.render_to_string(4, 20);
+        assert_eq!(shown.lines().skip(1).collect::<Vec<_>>(), lines[..3]);
+
+        while state.handle_key(Key::Down) {}
+        let shown = state.render_to_string(4, 20);
+        assert_eq!(
+            shown.lines().skip(1).collect::<Vec<_>>(),
+            lines[lines.len() - 3..]
+        );
+        assert!(!state.handle_key(Key::Down));
+
+        assert!(state.handle_key(Key::Esc));
+        assert!(!state.details);
+        state.handle_key(Key::Char('i'));
+        assert_eq!(state.detail_scroll, 0);
+    }
+
+    #[test]
+    fn paths_are_shown_by_cwd_display() {
+        let home = Some("/home/me/");
+        let cases = [
+            (
+                "/home/me/src/harpoon",
+                "/home/me/src/harpoon",
+                "~/src/harpoon",
+                "harpoon",
+            ),
+            ("/home/me", "/home/me", "~", "me"),
+            (
+                "/home/meg/notes",
+                "/home/meg/notes",
+                "/home/meg/notes",
+                "notes",
+            ),
+            ("/etc/nginx/", "/etc/nginx/", "/etc/nginx/", "nginx"),
+            ("~/src", "/home/me/src", "~/src", "src"),
+            ("/", "/", "/", "/"),
+        ];
+        for (path, absolute, relative, basename) in cases {
+            assert_eq!(CwdDisplay::Absolute.format(path, home), absolute);
+            assert_eq!(CwdDisplay::Relative.format(path, home), relative);
+            assert_eq!(CwdDisplay::Basename.format(path, home), basename);
+        }
+        assert_eq!(
+            CwdDisplay::Relative.format("/home/me/src", None),
+            "/home/me/src"
+        );
+        assert_eq!(CwdDisplay::Absolute.format("~/src", None), "~/src");
+
+        let mut state = state_with(&["/home/me/src", "nvim /home/me"]);
+        state.config.home_dir = Some("/home/me".to_string());
+        assert_eq!(state.capped_title(&state.panes[0]), "~/src");
+        assert_eq!(state.capped_title(&state.panes[1]), "nvim /home/me");
+    }
+
+    #[test]
+    fn preview_marks_one_pane_per_throttle_window() {
+        let mut state = state_with(&["a", "b", "c"]);
+        state.handle_key(Key::Down);
+        assert!(state.preview.is_none());
+
+        state.config.preview_on_navigate = true;
+        assert!(state.preview_selected());
+        assert!(!state.preview_selected());
+        state.handle_key(Key::Down);
+        state.handle_key(Key::Down);
+        assert_eq!(state.preview.as_ref().map(|p| p.pane_id), Some(1));
+
+        // The mark isn't taken for the pane's title.
+        let mut panes: Vec<PaneInfo> = state.panes.iter().map(|p| p.pane_info.clone()).collect();
+        panes[1].title = PREVIEW_NAME.to_string();
+        let manifest = PaneManifest {
+            panes: HashMap::from([(0, panes)]),
+        };
+        state.update(Event::PaneUpdate(manifest));
+        assert_eq!(state.panes[1].pane_info.title, "b");
+
+        state.preview.as_mut().unwrap().at -= PREVIEW_THROTTLE;
+        assert!(state.preview_selected());
+    }
+
+    #[test]
+    fn replacing_by_a_preset_makes_it_active() {
+        let mut state = state_with(&["a"]);
+        state.replace_list(
+            vec![PaneBookmark::from(&pane(9, "c"))],
+            Some("web".to_string()),
+        );
+        assert_eq!(state.persistence.active_preset, None);
+        state.handle_key(Key::Char('y'));
+        assert_eq!(state.persistence.active_preset.as_deref(), Some("web"));
+        assert!(state.footer().contains("[preset:web]"));
+
+        state.replace_list(Vec::new(), None);
+        state.handle_key(Key::Char('y'));
+        assert_eq!(state.persistence.active_preset, None);
+    }
+
+    #[test]
+    fn separator_rows_count_in_the_window() {
+        let mut state = state_with(&["a", "b", "c", "d", "e", "f"]);
+        for pane in &mut state.panes[3..] {
+            pane.tab_info.name = "Tab #2".to_string();
+        }
+        state.config.color = false;
+        state.config.selection_marker = String::new();
+        state.config.show_footer = false;
+        state.selected = 4;
+        let window = |state: &State| -> Vec<String> {
+            let shown = state.render_to_string(4, 40);
+            shown
+                .lines()
+                .map(|l| l.rsplit("| ").next().unwrap().to_string())
+                .collect()
+        };
+
+        assert_eq!(state.scroll_window(4), Some((1, 4)));
+        assert_eq!(window(&state), ["b", "c", "d", "e"]);
+        state.config.tab_separator = TabSeparator::Inline;
+        assert_eq!(state.scroll_window(4), Some((1, 4)));
+        state.config.color = true;
+        let underlined: Vec<bool> = (state.render_to_string(4, 40).lines())
+            .map(|l| l.contains("\x1b[4m"))
+            .collect();
+        assert_eq!(underlined, [false, true, false, false]);
+        state.config.color = false;
+
+        state.config.tab_separator = TabSeparator::Row;
+        assert_eq!(state.scroll_window(4), Some((2, 4)));
+        assert_eq!(window(&state), ["c", "── Tab #2", "d", "e"]);
+    }
+
+    #[test]
+    fn color_never_renders_plain_text() {
+        let mut errors = Vec::new();
+        let configuration = BTreeMap::from([("color".to_string(), "never".to_string())]);
+        let mut state = state_with(&["a", "b"]);
+        state.config = Config::from_configuration(&configuration, &mut errors);
+        state.config.show_footer = true;
+        state.locked = true;
+        let shown = state.render_to_string(10, 80);
+        assert!(!shown.contains('\x1b'));
+        assert!(shown.contains("> Tab #1 | a"));
+
+        let configuration = BTreeMap::from([("NO_COLOR".to_string(), "1".to_string())]);
+        state.config = Config::from_configuration(&configuration, &mut errors);
+        assert!(!state.render_to_string(10, 80).contains('\x1b'));
+        state.config = Config::default();
+        assert!(state.render_to_string(10, 80).contains('\x1b'));
+    }
+
+    #[test]
+    fn collapsed_tab_labels_show_once_per_tab() {
+        let mut state = state_with(&["a", "b", "c"]);
+        state.panes[2].tab_info.name = "Tab #2".to_string();
+        state.config.color = false;
+        state.config.collapse_tab_labels = true;
+        let shown = state.render_to_string(10, 80);
+        let lines: Vec<&str> = shown.lines().collect();
+        assert_eq!(lines, ["> Tab #1 | a", "         | b", "  Tab #2 | c"]);
+    }
+
+    #[test]
+    fn bar_lists_numbered_panes_within_the_width() {
+        let mut state = state_with(&["alpha", "beta", "gamma"]);
+        state.config.color = false;
+        state.config.selection_marker = "*".to_string();
+        assert_eq!(state.render_to_string(1, 80), "*1 alpha  2 beta  3 gamma");
+        assert_eq!(state.render_to_string(1, 14), "*1 alpha  2 b…");
+
+        // The selection stays in view, dropping leading panes.
+        state.selected = 2;
+        assert_eq!(state.render_to_string(1, 14), "*3 gamma");
+    }
+
+    #[test]
+    fn help_lists_the_configured_keys() {
+        let mut errors = Vec::new();
+        let configuration = BTreeMap::from([("add_key".to_string(), "x, ctrl+a".to_string())]);
+        let mut state = state_with(&["a"]);
+        state.config.color = false;
+        state.config.keymap = Keymap::from_configuration(&configuration, &mut errors);
+        state.handle_key(Key::Char('?'));
+        let shown = state.render_to_string(60, 80);
+        let add = shown
+            .lines()
+            .find(|l| l.ends_with("Add the focused pane"))
+            .unwrap();
+        assert!(add.starts_with("x, ctrl+a "));
+        assert!(shown
+            .lines()
+            .any(|l| l.starts_with("? ") && l.contains("this help")));
+        assert!(!shown.contains("Tab #1 | a"));
+        state.handle_key(Key::Char('?'));
+        assert!(state.render_to_string(60, 80).contains("Tab #1 | a"));
+    }
+
+    #[test]
+    fn same_titles_in_a_tab_are_numbered() {
+        let mut state = state_with(&["zsh", "zsh", "zsh", "nvim"]);
+        state.config.color = false;
+        state.pane_manifest = Some(PaneManifest {
+            panes: HashMap::from([(0, state.panes.iter().map(|p| p.pane_info.clone()).collect())]),
+        });
+        let shown = state.render_to_string(10, 80);
+        let lines: Vec<&str> = shown.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "> Tab #1 | zsh (1)",
+                "  Tab #1 | zsh (2)",
+                "  Tab #1 | zsh (3)",
+                "  Tab #1 | nvim"
+            ]
+        );
+    }
+
+    #[test]
+    fn selection_styles_paint_the_selected_row() {
+        let mut state = state_with(&["a", "b"]);
+        let mut rows = Vec::new();
+        for style in [
+            SelectionStyle::Fg,
+            SelectionStyle::Bg,
+            SelectionStyle::Invert,
+        ] {
+            state.config.selection_style = style;
+            let shown = state.render_to_string(10, 80);
+            let lines: Vec<&str> = shown.lines().collect();
+            assert_eq!(lines[1], "Tab #1 | b");
+            rows.push(lines[0].to_string());
+        }
+        assert!(rows[0].contains("\x1b[31m"));
+        assert!(rows[1].contains("\x1b[100m"));
+        assert!(rows[2].contains("\x1b[7m"));
+    }
+
+    #[test]
+    fn long_titles_are_capped_below_the_width() {
+        let mut state = state_with(&["cargo watch -x test"]);
+        state.config.color = false;
+        state.config.max_title_len = Some(8);
+        assert_eq!(state.render_to_string(10, 80), "> Tab #1 | cargo w…");
+        assert_eq!(state.render_to_string(10, 15), "> Tab #1 | car…");
+    }
+
+    #[test]
+    fn gutter_marks_only_the_selected_row() {
+        let mut state = state_with(&["a", "a long title"]);
+        state.config.selection_style = SelectionStyle::Gutter;
+        let shown = state.render_to_string(10, 14);
+        let lines: Vec<String> = shown.lines().map(plain).collect();
+        assert_eq!(lines, ["▎Tab #1 | a", " Tab #1 | a l…"]);
+        assert!(lines.iter().all(|line| line.chars().count() <= 14));
+    }
+
+    #[test]
+    fn footer_reflects_the_active_modes() {
+        let mut state = state_with(&["a", "b"]);
+        state.config.show_footer = true;
+        state.config.reverse = true;
+        state.locked = true;
+        let shown = state.render_to_string(10, 80);
+        assert_eq!(
+            plain(shown.lines().next_back().unwrap()),
+            "[sort:tab] [reverse] [locked]"
+        );
+        let narrow = state.render_to_string(10, 12);
+        assert!(plain(narrow.lines().last().unwrap()).chars().count() <= 12);
+    }
+
+    #[test]
+    fn pinned_panes_stay_first_and_survive_a_close() {
+        let mut state = state_with(&["a", "b", "c"]);
+        state.selected = 2;
+        state.handle_action(Action::Pin);
+        assert_eq!(titles(&state), ["c", "a", "b"]);
+        assert_eq!(state.selected, 0);
+        state.selected = 2;
+        state.handle_action(Action::Pin);
+        assert_eq!(titles(&state), ["c", "b", "a"]);
+
+        // "c" is closed but stays listed as a placeholder, while unpinned "a" is dropped.
+        let open = vec![state.panes[1].pane_info.clone()];
+        state.update(Event::TabUpdate(vec![TabInfo::default()]));
+        state.update(Event::PaneUpdate(PaneManifest {
+            panes: HashMap::from([(0, open)]),
+        }));
+        assert_eq!(titles(&state), ["c", "b"]);
+        assert!(state.panes[0].closed);
+        assert!(!state.panes[1].closed);
+    }
+
+    #[test]
+    fn compact_removes_only_stale_unpinned_panes() {
+        let mut state = state_with(&["live", "stale", "pinned", "stale too"]);
+        state.panes[2].pinned = true;
+        state.pane_manifest = Some(PaneManifest {
+            panes: HashMap::from([(0, vec![state.panes[0].pane_info.clone()])]),
+        });
+        state.handle_action(Action::Compact);
+        assert_eq!(titles(&state), ["live", "pinned"]);
+        assert_eq!(state.message.as_deref(), Some("Removed 2 stale bookmarks"));
+    }
+
+    #[test]
+    fn browse_lists_terminal_panes_of_every_tab() {
+        let mut state = state_with(&[]);
+        let tab = |position: usize, name: &str| TabInfo {
+            position,
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let terminal = |id: u32, title: &str| PaneInfo {
+            id,
+            title: title.to_string(),
+            ..Default::default()
+        };
+        let plugin = PaneInfo {
+            id: 9,
+            title: "harpoon".to_string(),
+            is_plugin: true,
+            ..Default::default()
+        };
+        state.update(Event::TabUpdate(vec![tab(0, "code"), tab(1, "logs")]));
+        state.update(Event::PaneUpdate(PaneManifest {
+            panes: HashMap::from([
+                (0, vec![terminal(1, "vim"), plugin]),
+                (1, vec![terminal(2, "tail")]),
+            ]),
+        }));
+        state.handle_action(Action::Browse);
+        let browsed: Vec<String> = (state.browse.as_ref().unwrap().panes.iter())
+            .map(|p| format!("{}: {}", p.tab_info.name, p.pane_info.title))
+            .collect();
+        assert_eq!(browsed, ["code: vim", "logs: tail"]);
+
+        // Bookmarking from the browse list adds the selected pane; browse toggles back.
+        state.handle_action(Action::Down);
+        state.handle_action(Action::Add);
+        state.handle_action(Action::Browse);
+        assert!(state.browse.is_none());
+        assert_eq!(titles(&state), ["tail"]);
+    }
+
+    #[test]
+    fn move_to_top_and_bottom_keeps_selection() {
+        let mut state = state_with(&["a", "b", "c"]);
+        state.selected = 1;
+        state.handle_action(Action::MoveToTop);
+        assert_eq!(titles(&state), ["a", "b", "c"]);
+        assert!(state.message.is_some());
+
+        state.config.sort_mode = SortMode::Manual;
+        state.handle_action(Action::MoveToTop);
+        assert_eq!(titles(&state), ["b", "a", "c"]);
+        assert_eq!(state.selected, 0);
+        state.selected = 1;
+        state.handle_action(Action::MoveToBottom);
+        assert_eq!(titles(&state), ["b", "c", "a"]);
+        assert_eq!(state.selected, 2);
+    }
+
+    #[test]
+    fn count_prefix_repeats_motions() {
+        let titles: Vec<String> = (0..10).map(|i| format!("pane {}", i)).collect();
+        let mut state = state_with(&titles.iter().map(String::as_str).collect::<Vec<_>>());
+        state.handle_key(Key::Char('3'));
+        state.handle_key(Key::Char('j'));
+        assert_eq!(state.selected, 3);
+        state.selected = 8;
+        state.handle_key(Key::Char('5'));
+        state.handle_key(Key::Char('k'));
+        assert_eq!(state.selected, 3);
+
+        // Any other key drops the count, so the next motion moves once.
+        state.handle_key(Key::Char('4'));
+        state.handle_key(Key::Char('z'));
+        assert_eq!(state.pending_count, None);
+        state.handle_key(Key::Char('j'));
+        assert_eq!(state.selected, 4);
+    }
+
+    #[test]
+    fn run_commands_is_requested_only_with_persistence() {
+        let mut errors = Vec::new();
+        let app_state = [
+            PermissionType::ReadApplicationState,
+            PermissionType::ChangeApplicationState,
+        ];
+        let configuration = BTreeMap::from([("persistence".to_string(), "off".to_string())]);
+        let config = Config::from_configuration(&configuration, &mut errors);
+        assert_eq!(config.permissions(), app_state);
+        let config = Config::from_configuration(&BTreeMap::new(), &mut errors);
+        assert_eq!(
+            config.permissions(),
+            [app_state.as_slice(), &[PermissionType::RunCommands]].concat()
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn panes_update_from_tabs_or_manifest_alone() {
+        let renamed = TabInfo {
+            name: "code".to_string(),
+            active: true,
+            ..Default::default()
+        };
+        let mut state = state_with(&["a", "b"]);
+        state.update(Event::TabUpdate(vec![renamed.clone()]));
+        assert!(state.panes.iter().all(|p| p.tab_info.name == "code"));
+        assert!(state.focused_pane.is_none());
+
+        // Only the manifest: "a" is retitled and focused, "b" is gone.
+        let retitled = PaneInfo {
+            id: 0,
+            title: "vim".to_string(),
+            is_focused: true,
+            ..Default::default()
+        };
+        let manifest = PaneManifest {
+            panes: HashMap::from([(0, vec![retitled])]),
+        };
+        let mut state = state_with(&["a", "b"]);
+        state.update(Event::PaneUpdate(manifest.clone()));
+        assert_eq!(titles(&state), ["vim"]);
+        assert!(state.focused_pane.is_none());
+
+        // Once both arrived, the focused pane is known as well.
+        state.update(Event::TabUpdate(vec![renamed]));
+        assert_eq!(state.focused_pane.unwrap().pane_info.title, "vim");
+    }
+
+    #[test]
+    fn tags_are_parsed_from_the_title_start() {
+        assert_eq!(parse_tag("#work nvim", "#").as_deref(), Some("#work"));
+        assert_eq!(parse_tag("#work", "#").as_deref(), Some("#work"));
+        assert_eq!(parse_tag("nvim #work", "#"), None);
+        assert_eq!(parse_tag("# nvim", "#"), None);
+        assert_eq!(parse_tag("#work nvim", ""), None);
+        assert_eq!(parse_tag("@ops htop", "@").as_deref(), Some("@ops"));
+    }
+
+    #[test]
+    fn tag_query_shows_only_that_tag() {
+        let mut state = state_with(&[]);
+        for (id, title) in (0..).zip(["#work nvim", "#home zsh", "#work htop"]) {
+            state.add_pane(pane(id, title));
+        }
+        state.search = Some(Search {
+            query: "#work".to_string(),
+            ..Default::default()
+        });
+        let shown = plain(&state.render_to_string(10, 80));
+        assert!(shown.contains("Tab #1 | nvim"));
+        assert!(shown.contains("Tab #1 | htop"));
+        assert!(!shown.contains("zsh"));
+        assert!(!shown.contains("#work nvim"));
+    }
+
+    #[test]
+    fn import_pipe_merges_bookmarks() {
+        let mut state = state_with(&["a"]);
+        let terminal = |id: u32, title: &str| PaneInfo {
+            id,
+            title: title.to_string(),
+            ..Default::default()
+        };
+        state.update(Event::TabUpdate(vec![TabInfo {
+            name: "Tab #1".to_string(),
+            ..Default::default()
+        }]));
+        state.update(Event::PaneUpdate(PaneManifest {
+            panes: HashMap::from([(0, vec![terminal(0, "a"), terminal(1, "b")])]),
+        }));
+        let payload = r#"{"bookmarks": [
+            {"tab_name": "Tab #1", "pane_title": "a"},
+            {"tab_name": "Tab #1", "pane_title": "b"},
+            {"tab_name": "Tab #1", "pane_title": "gone"}
+        ]}"#;
+        let pipe_message = PipeMessage {
+            source: PipeSource::Cli("test".to_string()),
+            name: IMPORT_PIPE.to_string(),
+            payload: Some(payload.to_string()),
+            args: BTreeMap::new(),
+            is_private: false,
+        };
+        state.handle_pipe_message(&pipe_message);
+        assert_eq!(titles(&state), ["a", "b"]);
+        assert_eq!(state.persistence.pending_bookmarks.len(), 1);
+        assert_eq!(
+            state.message.as_deref(),
+            Some("Imported 2 bookmarks (1 panes found)")
+        );
+    }
+
+    #[test]
+    fn type_ahead_cycles_through_matches() {
+        let mut state = state_with(&["vim", "zsh", "Vifm", "htop"]);
+        state.type_ahead('v');
+        assert_eq!(state.selected, 2);
+        state.type_ahead('v');
+        assert_eq!(state.selected, 0);
+        state.type_ahead('v');
+        assert_eq!(state.selected, 2);
+
+        state.type_ahead('x');
+        assert_eq!(state.selected, 2);
+        assert_eq!(state.message.as_deref(), Some("No pane starting with x"));
+    }
+
+    #[test]
+    fn bookmarks_follow_reordered_tabs() {
+        let tab = |position: usize, name: &str| TabInfo {
+            position,
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let terminal = |id: u32, title: &str| PaneInfo {
+            id,
+            title: title.to_string(),
+            ..Default::default()
+        };
+        let mut state = state_with(&[]);
+        state.update(Event::TabUpdate(vec![
+            tab(0, "a"),
+            tab(1, "b"),
+            tab(2, "c"),
+        ]));
+        state.update(Event::PaneUpdate(PaneManifest {
+            panes: HashMap::from([
+                (0, vec![terminal(1, "vim")]),
+                (1, vec![terminal(2, "zsh")]),
+                (2, vec![terminal(3, "htop")]),
+            ]),
+        }));
+        state.handle_action(Action::AddAll);
+
+        // Tabs "a" and "c" swap places.
+        state.update(Event::TabUpdate(vec![
+            tab(0, "c"),
+            tab(1, "b"),
+            tab(2, "a"),
+        ]));
+        state.update(Event::PaneUpdate(PaneManifest {
+            panes: HashMap::from([
+                (0, vec![terminal(3, "htop")]),
+                (1, vec![terminal(2, "zsh")]),
+                (2, vec![terminal(1, "vim")]),
+            ]),
+        }));
+        let mut resolved: Vec<String> = (state.panes.iter())
+            .map(|p| format!("{} {}", p.tab_info.position, p))
+            .collect();
+        resolved.sort();
+        assert_eq!(resolved, ["0 c | htop", "1 b | zsh", "2 a | vim"]);
+    }
+
+    #[test]
+    fn kept_closed_pane_is_rematched_when_reopened() {
+        let mut state = state_with(&["vim", "zsh"]);
+        state.config.on_pane_close = OnPaneClose::Keep;
+        let tab_info = TabInfo {
+            name: "Tab #1".to_string(),
+            ..Default::default()
+        };
+        let zsh = state.panes[1].pane_info.clone();
+        state.update(Event::TabUpdate(vec![tab_info]));
+        state.update(Event::PaneUpdate(PaneManifest {
+            panes: HashMap::from([(0, vec![zsh.clone()])]),
+        }));
+        assert_eq!(titles(&state), ["vim", "zsh"]);
+        assert!(state.panes[0].closed);
+        assert!(plain(&state.render_to_string(10, 80)).contains("vim"));
+
+        // A new pane with the same tab and title takes the placeholder's place.
+        let reopened = PaneInfo {
+            id: 7,
+            title: "vim".to_string(),
+            ..Default::default()
+        };
+        state.update(Event::PaneUpdate(PaneManifest {
+            panes: HashMap::from([(0, vec![zsh, reopened])]),
+        }));
+        assert_eq!(titles(&state), ["vim", "zsh"]);
+        assert!(!state.panes[0].closed);
+        assert_eq!(state.panes[0].pane_info.id, 7);
+    }
+
+    #[test]
+    fn keys_render_unless_they_hide_the_plugin() {
+        let mut outcomes = Vec::new();
+        for (action, keys) in Config::default().keymap.bindings() {
+            for key in keys {
+                let mut state = state_with(&["a", "b", "c"]);
+                state.focused_pane = Some(pane(5, "d"));
+                let render = state.update(Event::Key(*key));
+                assert!(!(render && state.hid), "{:?} hid and rendered", action);
+                outcomes.push((*action, (render, state.hid)));
+            }
+        }
+        let outcome = |action| outcomes.iter().find(|(a, _)| *a == action).unwrap().1;
+        assert_eq!(outcome(Action::Down), (true, false));
+        assert_eq!(outcome(Action::Help), (true, false));
+        assert_eq!(outcome(Action::Add), (false, true));
+        assert_eq!(outcome(Action::Focus), (false, true));
+        assert_eq!(outcome(Action::Quit), (false, true));
+    }
+
+    #[test]
+    fn note_is_typed_and_kept_with_the_bookmark() {
+        let mut state = state_with(&["a", "b"]);
+        state.selected = 1;
+        state.update(Event::Key(Key::Char('n')));
+        for c in " why it's here \n".chars() {
+            state.update(Event::Key(Key::Char(c)));
+        }
+        assert_eq!(state.note_input, None);
+        assert_eq!(state.panes[1].note.as_deref(), Some("why it's here"));
+        assert!(state.dirty);
+        assert_eq!(
+            PaneBookmark::from(&state.panes[1]).note.as_deref(),
+            Some("why it's here")
+        );
+
+        // Esc leaves the note as it was, and an empty note removes it.
+        state.update(Event::Key(Key::Char('n')));
+        state.update(Event::Key(Key::Backspace));
+        state.update(Event::Key(Key::Esc));
+        assert_eq!(state.panes[1].note.as_deref(), Some("why it's here"));
+        state.update(Event::Key(Key::Char('n')));
+        for _ in 0.."why it's here".len() {
+            state.update(Event::Key(Key::Backspace));
+        }
+        state.update(Event::Key(Key::Char('\n')));
+        assert_eq!(state.panes[1].note, None);
+    }
+
+    #[test]
+    fn every_key_is_safe_on_an_empty_list() {
+        for (action, keys) in Config::default().keymap.bindings() {
+            for key in keys {
+                let mut state = state_with(&[]);
+                let render = state.update(Event::Key(*key));
+                assert!(state.panes.is_empty(), "{:?}", action);
+                assert_eq!(state.selected, 0, "{:?}", action);
+                if action.needs_bookmarks() {
+                    assert!(!render, "{:?} did something", action);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn marked_bookmarks_are_deleted_together() {
+        let mut state = state_with(&["a", "b", "c", "d"]);
+        for key in ['v', 'j', 'v', 'j', 'v', 'k', 'v'] {
+            state.update(Event::Key(Key::Char(key)));
+        }
+        assert_eq!(state.marked, HashSet::from([(0, 0), (2, 0)]));
+        let shown = plain(&state.render_to_string(10, 80));
+        assert!(shown.contains("✓ Tab #1 | a"));
+        assert!(shown.contains("  Tab #1 | b"));
+
+        state.update(Event::Key(Key::Char('D')));
+        assert_eq!(titles(&state), ["b", "d"]);
+        assert!(state.marked.is_empty());
+        assert!(state.selected < state.panes.len());
+
+        // Esc clears the marks without deleting anything.
+        state.update(Event::Key(Key::Char('v')));
+        state.update(Event::Key(Key::Esc));
+        assert!(state.marked.is_empty());
+        assert_eq!(titles(&state), ["b", "d"]);
+    }
+
+    #[test]
+    fn adding_a_bookmarked_pane_selects_it() {
+        let mut state = state_with(&["a", "b", "c"]);
+        state.focused_pane = Some(state.panes[1].clone());
+        assert!(state.handle_action(Action::Add));
+        assert_eq!(titles(&state), ["a", "b", "c"]);
+        assert_eq!(state.selected, 1);
+        assert_eq!(state.message.as_deref(), Some("Already bookmarked"));
+        assert!(!state.hid);
+    }
+
+    #[test]
+    fn indexed_lookup_matches_a_linear_scan() {
+        let pane_info = |id: u32, is_plugin: bool| PaneInfo {
+            id,
+            is_plugin,
+            ..Default::default()
+        };
+        let pane_manifest = PaneManifest {
+            panes: HashMap::from([
+                (
+                    0,
+                    vec![pane_info(1, false), pane_info(1, true), pane_info(4, false)],
+                ),
+                (1, vec![pane_info(2, false)]),
+                (
+                    3,
+                    vec![pane_info(7, true), pane_info(3, false), pane_info(9, false)],
+                ),
+            ]),
+        };
+        let linear = |pane_id: u32| {
+            pane_manifest.panes.iter().find_map(|(&position, panes)| {
+                let pane_info = panes.iter().find(|p| !p.is_plugin && p.id == pane_id)?;
+                Some((position, pane_info))
+            })
+        };
+        let pane_index = index_panes(&pane_manifest);
+        for pane_id in 0..12 {
+            assert_eq!(
+                find_pane(pane_id, &pane_index, &pane_manifest),
+                linear(pane_id),
+                "pane {}",
+                pane_id
+            );
+        }
+    }
+
+    #[test]
+    fn pin_all_toggles_every_bookmark_and_is_restored() {
+        let mut state = state_with(&["a", "b", "c"]);
+        state.panes[1].pinned = true;
+        state.handle_action(Action::PinAll);
+        assert!(state.panes.iter().all(|p| p.pinned));
+        assert_eq!(state.message.as_deref(), Some("Pinned 2 panes"));
+        assert!(state.dirty);
+
+        // The saved bookmarks bring the pins back once their panes are found again.
+        let mut persistence = Persistence::default();
+        persistence.pending_bookmarks = state.panes.iter().map(PaneBookmark::from).collect();
+        let pane_manifest = PaneManifest {
+            panes: HashMap::from([(0, state.panes.iter().map(|p| p.pane_info.clone()).collect())]),
+        };
+        let tab_infos = [state.panes[0].tab_info.clone()];
+        let restored = persistence.match_pending_bookmarks(&[], &pane_manifest, &tab_infos);
+        assert_eq!(restored.len(), 3);
+        assert!(restored.iter().all(|p| p.pinned));
+
+        state.handle_action(Action::PinAll);
+        assert!(state.panes.iter().all(|p| !p.pinned));
+        assert_eq!(state.message.as_deref(), Some("Unpinned 3 panes"));
+    }
+
+    #[test]
+    fn save_interval_writes_only_unsaved_changes() {
+        let mut state = state_with(&["a"]);
+        state.config.save_interval = Some(Duration::from_millis(1));
+        state.persistence.enabled = true;
+        state.persistence.set_session_name(Some("main".to_string()));
+        state.persistence.on_load_command(Some(1), b"");
+        state.update(Event::Timer(0.001));
+        assert!(state.last_interval_save.is_some());
+        assert!(!state.dirty);
+        assert!(!state.flush());
+
+        // Once there are unsaved changes, the next timer writes them.
+        state.dirty = true;
+        state.update(Event::Timer(0.001));
+        assert!(!state.dirty);
+    }
+
+    #[test]
+    fn switcher_pipe_searches_and_focuses_the_top_match() {
+        let mut state = state_with(&["zsh", "nvim", "htop"]);
+        let pipe_message = PipeMessage {
+            source: PipeSource::Keybind,
+            name: SWITCHER_PIPE.to_string(),
+            payload: None,
+            args: BTreeMap::new(),
+            is_private: false,
+        };
+        assert!(state.handle_pipe_message(&pipe_message));
+        assert!(state
+            .search
+            .as_ref()
+            .is_some_and(|s| s.editing && s.switcher));
+
+        for c in "ht\n".chars() {
+            state.update(Event::Key(Key::Char(c)));
+        }
+        assert!(state.search.is_none());
+        assert_eq!(state.selected, 2);
+        assert!(state.hid);
+    }
+
+    #[test]
+    fn max_rows_caps_the_window_in_a_taller_pane() {
+        let titles: Vec<String> = (0..10).map(|i| format!("pane {}", i)).collect();
+        let mut state = state_with(&titles.iter().map(String::as_str).collect::<Vec<_>>());
+        state.config.max_rows = Some(3);
+        state.selected = 5;
+        let shown = plain(&state.render_to_string(20, 80));
+        let rows: Vec<&str> = shown.lines().filter(|l| l.contains("| pane")).collect();
+        assert_eq!(
+            rows,
+            ["Tab #1 | pane 3", "Tab #1 | pane 4", "Tab #1 | pane 5"]
         );
     }
 }
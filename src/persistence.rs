@@ -0,0 +1,1060 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+use zellij_tile::prelude::*;
+
+use crate::{same_title_index, Pane, SortMode};
+
+/// Context key used to recognise the results of our own `run_command` calls.
+pub const CONTEXT_KEY: &str = "harpoon";
+const LOAD: &str = "load";
+const SAVE: &str = "save";
+const BASE_LOAD: &str = "base";
+const RELOAD: &str = "reload";
+const EXPORT: &str = "export";
+const PRESET_SAVE: &str = "preset_save";
+const PRESET_LOAD: &str = "preset_load";
+/// Context key holding the name of the preset a command was issued for.
+const PRESET_KEY: &str = "preset";
+
+/// Default directory bookmark files are stored in, one file per session.
+const DEFAULT_DATA_DIR: &str = "~/.local/share/zellij-harpoon";
+
+/// Bookmark files larger than this are rejected rather than parsed. Only one byte more is read,
+/// so a huge file doesn't flood the plugin.
+const MAX_FILE_BYTES: usize = 1024 * 1024;
+
+/// Largest piece of file contents passed as one command argument, well under Linux's limit.
+const MAX_ARG_BYTES: usize = 64 * 1024;
+
+/// Shell prelude expanding a leading `~/` of the path passed as `$1` into `$f`.
+const EXPAND_PATH: &str = r#"f="$1"; case "$f" in "~/"*) f="$HOME/${f#"~/"}";; esac;"#;
+
+/// The on-disk representation of a bookmark. Pane ids are not stable across sessions, so panes
+/// are matched back by tab name and pane title once they show up.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PaneBookmark {
+    pub tab_name: String,
+    /// Position of the tab, to pick between tabs with the same name on reload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tab_position: Option<usize>,
+    pub pane_title: String,
+    #[serde(default)]
+    pub pinned: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub geometry: Option<PaneGeometry>,
+    /// Position among the panes of the tab with the same title, to tell them apart on reload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title_index: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// Which copy of the bookmark this is, see `Pane::copy`.
+    #[serde(default, skip_serializing_if = "is_original")]
+    pub copy: u32,
+    /// Set for bookmarks of the base list, which are never written to the session file.
+    #[serde(skip)]
+    pub base: bool,
+    /// Number of pane updates this bookmark went unmatched, while pending.
+    #[serde(skip)]
+    pub misses: usize,
+}
+
+/// Where a pane was on screen, saved with `record_geometry`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PaneGeometry {
+    pub x: usize,
+    pub y: usize,
+    pub rows: usize,
+    pub cols: usize,
+    pub floating: bool,
+}
+
+impl From<&PaneInfo> for PaneGeometry {
+    fn from(pane_info: &PaneInfo) -> Self {
+        PaneGeometry {
+            x: pane_info.pane_x,
+            y: pane_info.pane_y,
+            rows: pane_info.pane_rows,
+            cols: pane_info.pane_columns,
+            floating: pane_info.is_floating,
+        }
+    }
+}
+
+impl From<&Pane> for PaneBookmark {
+    fn from(pane: &Pane) -> Self {
+        PaneBookmark {
+            tab_name: pane.tab_info.name.clone(),
+            tab_position: Some(pane.tab_info.position),
+            pane_title: pane.pane_info.title.clone(),
+            pinned: pane.pinned,
+            geometry: None,
+            title_index: None,
+            tag: pane.tag.clone(),
+            note: pane.note.clone(),
+            copy: pane.copy,
+            misses: 0,
+            base: pane.base,
+        }
+    }
+}
+
+/// How the list was last viewed, restored together with its bookmarks. Unset preferences
+/// keep the configured value.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ViewPreferences {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_mode: Option<SortMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collapse_tab_labels: Option<bool>,
+    /// First row of the list that was shown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scroll: Option<usize>,
+}
+
+/// Written into bookmark files so whoever opens one knows where it comes from.
+const GENERATED_BY: &str = "zellij-harpoon";
+
+/// The contents of a bookmark file.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BookmarkFile {
+    /// Informational only, ignored when loading.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generated_by: Option<String>,
+    /// The session the file belongs to. Informational only, ignored when loading.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    pub bookmarks: Vec<PaneBookmark>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub view: Option<ViewPreferences>,
+    /// The preset the list was last replaced by, restored with it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_preset: Option<String>,
+}
+
+/// Older JSON files hold a bare list of bookmarks.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredFile {
+    File(BookmarkFile),
+    Bookmarks(Vec<PaneBookmark>),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PersistFormat {
+    #[default]
+    Json,
+    Toml,
+}
+
+impl PersistFormat {
+    pub fn from_str(value: &str) -> Option<Self> {
+        match value.trim() {
+            "json" => Some(PersistFormat::Json),
+            "toml" => Some(PersistFormat::Toml),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PersistFormat::Json => "json",
+            PersistFormat::Toml => "toml",
+        }
+    }
+
+    pub fn serialize(&self, file: &BookmarkFile) -> Result<String, String> {
+        match self {
+            PersistFormat::Json => serde_json::to_string(file).map_err(|e| e.to_string()),
+            PersistFormat::Toml => toml::to_string(file).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn deserialize(&self, contents: &str) -> Result<BookmarkFile, String> {
+        match self {
+            PersistFormat::Json => match serde_json::from_str(contents) {
+                Ok(StoredFile::File(file)) => Ok(file),
+                Ok(StoredFile::Bookmarks(bookmarks)) => Ok(BookmarkFile {
+                    bookmarks,
+                    ..BookmarkFile::default()
+                }),
+                Err(e) => Err(e.to_string()),
+            },
+            PersistFormat::Toml => toml::from_str(contents).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Persistence {
+    /// Bookmarks are only kept in memory when disabled.
+    pub enabled: bool,
+    pub format: PersistFormat,
+    pub session_name: Option<String>,
+    /// Bookmarks read from disk whose panes have not been found in the session (yet).
+    pub pending_bookmarks: Vec<PaneBookmark>,
+    /// Bookmarks removed for this session only, which are still saved, with the key of the pane
+    /// they were removed from, see `Pane::key`.
+    pub kept_deletes: Vec<((u32, u32), PaneBookmark)>,
+    /// View preferences read from disk, until they are applied.
+    pub view: Option<ViewPreferences>,
+    /// The preset the list was last replaced by, if any. `harpoon::save_preset` without a name
+    /// saves to it.
+    pub active_preset: Option<String>,
+    /// Save the geometry of bookmarked panes. Zellij doesn't let plugins move or resize panes,
+    /// so it is only recorded for now.
+    pub record_geometry: bool,
+    /// Shared list merged underneath the session's own bookmarks, read after them.
+    pub base_list_path: Option<String>,
+    pub debug: bool,
+    permission_granted: bool,
+    load_requested: bool,
+    loaded: bool,
+    /// The session file couldn't be parsed; the next save moves it to `<file>.bak` first.
+    backup_pending: bool,
+}
+
+impl Persistence {
+    /// Number of bookmarks still waiting for their pane to show up.
+    pub fn pending_count(&self) -> usize {
+        self.pending_bookmarks.len()
+    }
+
+    pub fn session_file_path(&self) -> Option<String> {
+        let session_name = self.session_name.as_ref()?;
+        Some(format!(
+            "{}/{}.{}",
+            DEFAULT_DATA_DIR,
+            session_name,
+            self.format.extension()
+        ))
+    }
+
+    /// The file of the preset `name`, under the `presets` directory of the data dir. Names that
+    /// would leave that directory are refused.
+    pub fn preset_path(&self, name: &str) -> Option<String> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return None;
+        }
+        Some(format!(
+            "{}/presets/{}.{}",
+            DEFAULT_DATA_DIR,
+            name,
+            self.format.extension()
+        ))
+    }
+
+    /// Whether commands can be run, which persistence requests the permission for.
+    pub fn can_run_commands(&self) -> bool {
+        self.enabled && self.permission_granted
+    }
+
+    pub fn set_permission_granted(&mut self) {
+        self.permission_granted = true;
+        self.load_from_disk();
+    }
+
+    pub fn set_session_name(&mut self, session_name: Option<String>) {
+        self.session_name = session_name;
+        self.load_from_disk();
+    }
+
+    /// Issue the command reading this session's bookmarks, once both the session name and the
+    /// permission to run commands are known. The result arrives in `on_load_command`.
+    fn load_from_disk(&mut self) {
+        if !self.enabled || self.load_requested || !self.permission_granted {
+            return;
+        }
+        let Some(path) = self.session_file_path() else {
+            return;
+        };
+        self.load_requested = true;
+        debug_log!(self.debug, "load issued for {}", path);
+        let script = read_script();
+        run_command(&["sh", "-c", &script, "harpoon", &path], context(LOAD));
+    }
+
+    /// Stop keeping the removed bookmark of the pane with `key`, now that it is bookmarked again
+    /// and saved as such.
+    pub fn forget_kept_delete(&mut self, key: (u32, u32)) {
+        self.kept_deletes.retain(|(kept, _)| *kept != key);
+    }
+
+    /// Write `panes`, the still pending bookmarks and the view preferences to the session file.
+    /// Panes of the base list and ephemeral panes are left out.
+    /// Returns whether the write was issued. If the bookmarks can't be serialized the file is left
+    /// alone and the error is returned.
+    pub fn save_to_disk(
+        &mut self,
+        panes: &[Pane],
+        pane_manifest: Option<&PaneManifest>,
+        view: ViewPreferences,
+    ) -> Result<bool, String> {
+        if !self.enabled || !self.loaded {
+            // Never overwrite the file before it was read, or bookmarks of a previous run are lost.
+            return Ok(false);
+        }
+        let Some(path) = self.session_file_path() else {
+            return Ok(false);
+        };
+        let bookmarks: Vec<PaneBookmark> = (self.bookmarks(panes, pane_manifest).into_iter())
+            .chain(
+                self.kept_deletes
+                    .iter()
+                    .map(|(_, bookmark)| bookmark.clone()),
+            )
+            .collect();
+        let file = BookmarkFile {
+            generated_by: Some(GENERATED_BY.to_string()),
+            session: self.session_name.clone(),
+            bookmarks,
+            view: Some(view),
+            active_preset: self.active_preset.clone(),
+        };
+        let contents = match self.format.serialize(&file) {
+            Ok(contents) => contents,
+            Err(e) => {
+                debug_log!(self.debug, "save skipped, failed to serialize: {}", e);
+                return Err(e);
+            }
+        };
+        debug_log!(
+            self.debug,
+            "save issued for {} ({} bookmarks)",
+            path,
+            file.bookmarks.len()
+        );
+        write_file(&path, &contents, self.backup_pending, context(SAVE));
+        self.backup_pending = false;
+        Ok(true)
+    }
+
+    /// The bookmarks of `panes` and the still pending ones, leaving out the base list and
+    /// ephemeral panes.
+    fn bookmarks(&self, panes: &[Pane], pane_manifest: Option<&PaneManifest>) -> Vec<PaneBookmark> {
+        panes
+            .iter()
+            .filter(|pane| !pane.base && !pane.ephemeral)
+            .map(|pane| PaneBookmark {
+                geometry: self
+                    .record_geometry
+                    .then(|| PaneGeometry::from(&pane.pane_info)),
+                title_index: pane_manifest
+                    .and_then(|m| m.panes.get(&pane.tab_info.position))
+                    .and_then(|tab_panes| same_title_index(&pane.pane_info, tab_panes)),
+                ..PaneBookmark::from(pane)
+            })
+            .chain(self.pending_bookmarks.iter().filter(|b| !b.base).cloned())
+            .collect()
+    }
+
+    /// Write a snapshot of the list to the preset `name`, replacing an older one. Returns whether
+    /// the write was issued; the result arrives as a preset save result.
+    pub fn save_preset(
+        &self,
+        name: &str,
+        panes: &[Pane],
+        pane_manifest: Option<&PaneManifest>,
+    ) -> Result<bool, String> {
+        if !self.can_run_commands() {
+            return Ok(false);
+        }
+        let path = self
+            .preset_path(name)
+            .ok_or_else(|| format!("{:?} is not a valid preset name", name))?;
+        let file = BookmarkFile {
+            generated_by: Some(GENERATED_BY.to_string()),
+            session: self.session_name.clone(),
+            bookmarks: self.bookmarks(panes, pane_manifest),
+            view: None,
+            active_preset: None,
+        };
+        let contents = self.format.serialize(&file)?;
+        debug_log!(self.debug, "preset save issued for {}", path);
+        write_file(&path, &contents, false, preset_context(PRESET_SAVE, name));
+        Ok(true)
+    }
+
+    /// Read the preset `name`. Returns whether the read was issued; the bookmarks arrive in
+    /// `on_load_preset_command`.
+    pub fn load_preset(&self, name: &str) -> Result<bool, String> {
+        if !self.can_run_commands() {
+            return Ok(false);
+        }
+        let path = self
+            .preset_path(name)
+            .ok_or_else(|| format!("{:?} is not a valid preset name", name))?;
+        debug_log!(self.debug, "preset load issued for {}", path);
+        let script = read_script();
+        run_command(
+            &["sh", "-c", &script, "harpoon", &path],
+            preset_context(PRESET_LOAD, name),
+        );
+        Ok(true)
+    }
+
+    /// Handle the result of `load_preset`, returning the bookmarks of the preset.
+    pub fn on_load_preset_command(
+        &self,
+        exit_code: Option<i32>,
+        stdout: &[u8],
+        context: &BTreeMap<String, String>,
+    ) -> Result<(String, Vec<PaneBookmark>), String> {
+        let name = context.get(PRESET_KEY).cloned().unwrap_or_default();
+        if exit_code != Some(0) {
+            return Err(format!("No preset {}", name));
+        }
+        let contents = file_contents(stdout).map_err(|e| format!("preset {} is {}", name, e))?;
+        let bookmarks = self.format.deserialize(contents)?.bookmarks;
+        Ok((name, bookmarks))
+    }
+
+    /// Handle the result of a command issued by `load_from_disk`. A missing file simply means
+    /// there is nothing to restore yet. A file that can't be parsed is moved aside to a `.bak`
+    /// file by the next save, so it doesn't break persistence for good; the returned message
+    /// reports that. Once the
+    /// session's bookmarks are known, the base list is read.
+    pub fn on_load_command(&mut self, exit_code: Option<i32>, stdout: &[u8]) -> Option<String> {
+        self.loaded = true;
+        let notice = self.parse_session_file(exit_code, stdout);
+        self.load_base_list();
+        notice
+    }
+
+    fn parse_session_file(&mut self, exit_code: Option<i32>, stdout: &[u8]) -> Option<String> {
+        if exit_code != Some(0) {
+            debug_log!(self.debug, "load found no file (exit code {:?})", exit_code);
+            return None;
+        }
+        let contents = match file_contents(stdout) {
+            Ok(contents) => contents,
+            Err(e) => {
+                debug_log!(self.debug, "load result rejected: {}", e);
+                self.backup_pending = true;
+                return Some(format!("Bookmark file is {} (backup saved)", e));
+            }
+        };
+        if contents.trim().is_empty() {
+            debug_log!(self.debug, "load result parsed (0 bookmarks)");
+            return None;
+        }
+        match self.format.deserialize(contents) {
+            Ok(file) => {
+                debug_log!(
+                    self.debug,
+                    "load result parsed ({} bookmarks)",
+                    file.bookmarks.len()
+                );
+                self.pending_bookmarks = file.bookmarks;
+                self.view = file.view;
+                // A name that can't be a preset, e.g. after editing the file, leaves none active.
+                self.active_preset = (file.active_preset).filter(|p| self.preset_path(p).is_some());
+                None
+            }
+            Err(e) => {
+                debug_log!(self.debug, "load result failed to parse: {}", e);
+                self.pending_bookmarks.clear();
+                self.backup_pending = true;
+                Some("Recovered from corrupt bookmark file (backup saved)".to_string())
+            }
+        }
+    }
+
+    /// Read the session file again, e.g. after it was edited by hand. Returns whether the read
+    /// was issued; the result arrives in `on_reload_command`.
+    pub fn reload_from_disk(&self) -> bool {
+        if !self.enabled || !self.loaded {
+            return false;
+        }
+        let Some(path) = self.session_file_path() else {
+            return false;
+        };
+        debug_log!(self.debug, "reload issued for {}", path);
+        let script = read_script();
+        run_command(&["sh", "-c", &script, "harpoon", &path], context(RELOAD));
+        true
+    }
+
+    /// Handle the result of `reload_from_disk`, merging the bookmarks of the file that aren't
+    /// in `panes` or pending yet. Returns how many were new.
+    pub fn on_reload_command(
+        &mut self,
+        exit_code: Option<i32>,
+        stdout: &[u8],
+        panes: &[Pane],
+    ) -> Result<usize, String> {
+        if exit_code != Some(0) {
+            return Err("No bookmark file to reload".to_string());
+        }
+        let contents = file_contents(stdout).map_err(|e| format!("bookmark file is {}", e))?;
+        let file = self.format.deserialize(contents)?;
+        let new = self.merge_bookmarks(file.bookmarks, panes);
+        debug_log!(self.debug, "reload merged {} new bookmarks", new);
+        Ok(new)
+    }
+
+    fn load_base_list(&self) {
+        let Some(path) = &self.base_list_path else {
+            return;
+        };
+        debug_log!(self.debug, "base list load issued for {}", path);
+        let script = read_script();
+        run_command(&["sh", "-c", &script, "harpoon", path], context(BASE_LOAD));
+    }
+
+    /// Handle the result of reading the base list. Its bookmarks are added after the session's
+    /// own, skipping those already in `panes` or pending. The format follows the file extension.
+    pub fn on_base_load_command(
+        &mut self,
+        exit_code: Option<i32>,
+        stdout: &[u8],
+        panes: &[Pane],
+    ) -> Option<String> {
+        let path = self.base_list_path.as_deref()?;
+        if exit_code != Some(0) {
+            return Some(format!("Could not read base list {}", path));
+        }
+        let format = if path.ends_with(".toml") {
+            PersistFormat::Toml
+        } else {
+            PersistFormat::Json
+        };
+        let contents = match file_contents(stdout) {
+            Ok(contents) => contents,
+            Err(e) => return Some(format!("Base list {} is {}", path, e)),
+        };
+        let file = match format.deserialize(contents) {
+            Ok(file) => file,
+            Err(e) => {
+                debug_log!(self.debug, "base list failed to parse: {}", e);
+                return Some(format!("Could not parse base list {}", path));
+            }
+        };
+        let bookmarks = file
+            .bookmarks
+            .into_iter()
+            .map(|bookmark| PaneBookmark {
+                base: true,
+                ..bookmark
+            })
+            .collect();
+        self.merge_bookmarks(bookmarks, panes);
+        debug_log!(
+            self.debug,
+            "base list parsed, {} bookmarks pending",
+            self.pending_bookmarks.len()
+        );
+        None
+    }
+
+    /// Whether the session file still has to be moved aside by a save.
+    pub fn backup_pending(&self) -> bool {
+        self.backup_pending
+    }
+
+    /// Count a pane update in which the pending bookmarks found no pane.
+    pub fn count_misses(&mut self) {
+        for bookmark in &mut self.pending_bookmarks {
+            bookmark.misses += 1;
+        }
+    }
+
+    /// Drop pending bookmarks that went unmatched for `max_misses` pane updates, as their panes
+    /// are likely gone for good. Returns how many were dropped.
+    pub fn prune_pending(&mut self, max_misses: usize) -> usize {
+        let before = self.pending_bookmarks.len();
+        self.pending_bookmarks.retain(|b| b.misses < max_misses);
+        let pruned = before - self.pending_bookmarks.len();
+        if pruned > 0 {
+            debug_log!(self.debug, "pruned {} pending bookmarks", pruned);
+        }
+        pruned
+    }
+
+    /// Queue `bookmarks` to be matched against the session, skipping those whose tab name and
+    /// pane title are already in `panes` or pending. Returns how many were queued.
+    pub fn merge_bookmarks(&mut self, bookmarks: Vec<PaneBookmark>, panes: &[Pane]) -> usize {
+        let mut known: HashSet<(String, String, u32)> = panes
+            .iter()
+            .map(|p| (p.tab_info.name.clone(), p.pane_info.title.clone(), p.copy))
+            .chain(
+                self.pending_bookmarks
+                    .iter()
+                    .map(|b| (b.tab_name.clone(), b.pane_title.clone(), b.copy)),
+            )
+            .collect();
+        let before = self.pending_bookmarks.len();
+        for bookmark in bookmarks {
+            let key = (
+                bookmark.tab_name.clone(),
+                bookmark.pane_title.clone(),
+                bookmark.copy,
+            );
+            if known.insert(key) {
+                self.pending_bookmarks.push(bookmark);
+            }
+        }
+        self.pending_bookmarks.len() - before
+    }
+
+    pub fn is_load_result(context: &BTreeMap<String, String>) -> bool {
+        context.get(CONTEXT_KEY).map(String::as_str) == Some(LOAD)
+    }
+
+    pub fn is_reload_result(context: &BTreeMap<String, String>) -> bool {
+        context.get(CONTEXT_KEY).map(String::as_str) == Some(RELOAD)
+    }
+
+    pub fn is_base_load_result(context: &BTreeMap<String, String>) -> bool {
+        context.get(CONTEXT_KEY).map(String::as_str) == Some(BASE_LOAD)
+    }
+
+    pub fn is_export_result(context: &BTreeMap<String, String>) -> bool {
+        context.get(CONTEXT_KEY).map(String::as_str) == Some(EXPORT)
+    }
+
+    pub fn is_preset_save_result(context: &BTreeMap<String, String>) -> bool {
+        context.get(CONTEXT_KEY).map(String::as_str) == Some(PRESET_SAVE)
+    }
+
+    pub fn is_preset_load_result(context: &BTreeMap<String, String>) -> bool {
+        context.get(CONTEXT_KEY).map(String::as_str) == Some(PRESET_LOAD)
+    }
+
+    /// Write `contents` to `path`, e.g. an exported layout. Returns whether the write was issued,
+    /// which requires the permission to run commands.
+    pub fn write_export(&self, path: &str, contents: &str) -> bool {
+        if !self.can_run_commands() {
+            return false;
+        }
+        debug_log!(self.debug, "export issued for {}", path);
+        write_file(path, contents, false, context(EXPORT));
+        true
+    }
+
+    /// Turn pending bookmarks whose tab name and pane title match a pane of the session into
+    /// panes, skipping panes that are already bookmarked. A bookmark leaves the pending list as
+    /// soon as it matches, and its pane is marked used right away, so each bookmark matches at
+    /// most one pane and each pane at most one bookmark, however tab names repeat. Copies made
+    /// with the duplicate action count apart, so they match the pane of their original.
+    ///
+    /// Ties are broken in a fixed order: of several tabs with that name, the one at the saved tab
+    /// position comes first, then the others by lowest position. Within a tab, the pane at the
+    /// saved `title_index` is preferred, then the pane with the lowest id.
+    pub fn match_pending_bookmarks(
+        &mut self,
+        panes: &[Pane],
+        pane_manifest: &PaneManifest,
+        tab_infos: &[TabInfo],
+    ) -> Vec<Pane> {
+        let mut used: HashSet<(u32, u32)> = panes.iter().map(Pane::key).collect();
+        let mut matched = Vec::new();
+        self.pending_bookmarks.retain_mut(|bookmark| {
+            let mut tabs: Vec<&TabInfo> = tab_infos
+                .iter()
+                .filter(|t| t.name == bookmark.tab_name)
+                .collect();
+            tabs.sort_by_key(|t| (Some(t.position) != bookmark.tab_position, t.position));
+            for tab_info in tabs {
+                let Some(tab_panes) = pane_manifest.panes.get(&tab_info.position) else {
+                    continue;
+                };
+                let candidates: Vec<&PaneInfo> = tab_panes
+                    .iter()
+                    .filter(|p| !p.is_plugin && p.title == bookmark.pane_title)
+                    .collect();
+                // Prefer the pane at the saved position among panes with the same title.
+                let preferred = bookmark
+                    .title_index
+                    .and_then(|idx| candidates.get(idx))
+                    .filter(|p| !used.contains(&(p.id, bookmark.copy)));
+                let unused = candidates
+                    .iter()
+                    .filter(|p| !used.contains(&(p.id, bookmark.copy)))
+                    .min_by_key(|p| p.id);
+                if let Some(&pane_info) = preferred.or(unused) {
+                    used.insert((pane_info.id, bookmark.copy));
+                    let mut pane = Pane::new(pane_info.clone(), tab_info.clone());
+                    pane.pinned = bookmark.pinned;
+                    pane.tag = bookmark.tag.clone();
+                    pane.note = bookmark.note.clone();
+                    pane.base = bookmark.base;
+                    pane.copy = bookmark.copy;
+                    matched.push(pane);
+                    return false;
+                }
+            }
+            true
+        });
+        if !matched.is_empty() {
+            debug_log!(
+                self.debug,
+                "matched {} bookmarks, {} still pending",
+                matched.len(),
+                self.pending_bookmarks.len()
+            );
+        }
+        matched
+    }
+}
+
+fn is_original(copy: &u32) -> bool {
+    *copy == 0
+}
+
+/// Script printing the file at `$1`, cut one byte past `MAX_FILE_BYTES`.
+fn read_script() -> String {
+    format!(r#"{} head -c {} "$f""#, EXPAND_PATH, MAX_FILE_BYTES + 1)
+}
+
+/// The contents of a bookmark file as read by `read_script`, unless it is too large or not
+/// valid UTF-8.
+fn file_contents(stdout: &[u8]) -> Result<&str, String> {
+    if stdout.len() > MAX_FILE_BYTES {
+        return Err(format!("larger than {} bytes", MAX_FILE_BYTES));
+    }
+    std::str::from_utf8(stdout).map_err(|_| "not valid UTF-8".to_string())
+}
+
+/// Write `contents` to the file at `path`, creating its directory first. With `backup`, an
+/// existing file is moved to `<file>.bak` in the same command, so the write can't overtake it.
+/// The contents are passed as several arguments, since Linux caps a single one at 128 KiB.
+fn write_file(path: &str, contents: &str, backup: bool, context: BTreeMap<String, String>) {
+    let backup = if backup {
+        r#"{ [ ! -e "$f" ] || mv -f "$f" "$f.bak"; } &&"#
+    } else {
+        ""
+    };
+    let script = format!(
+        r#"{} mkdir -p "$(dirname "$f")" && {} shift && printf '%s' "$@" > "$f""#,
+        EXPAND_PATH, backup
+    );
+    let mut args = vec!["sh", "-c", &script, "harpoon", path];
+    args.extend(chunks(contents, MAX_ARG_BYTES));
+    run_command(&args, context);
+}
+
+/// Split `text` into pieces of at most `max` bytes, on character boundaries.
+fn chunks(text: &str, max: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while rest.len() > max {
+        let mut end = max;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (piece, tail) = rest.split_at(end);
+        pieces.push(piece);
+        rest = tail;
+    }
+    pieces.push(rest);
+    pieces
+}
+
+fn context(action: &str) -> BTreeMap<String, String> {
+    BTreeMap::from([(CONTEXT_KEY.to_string(), action.to_string())])
+}
+
+fn preset_context(action: &str, name: &str) -> BTreeMap<String, String> {
+    let mut context = context(action);
+    context.insert(PRESET_KEY.to_string(), name.to_string());
+    context
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn bookmark(title: &str, copy: u32) -> PaneBookmark {
+        let pane_info = PaneInfo {
+            title: title.to_string(),
+            ..Default::default()
+        };
+        let tab_info = TabInfo {
+            name: "Tab #1".to_string(),
+            ..Default::default()
+        };
+        PaneBookmark {
+            copy,
+            ..PaneBookmark::from(&Pane::new(pane_info, tab_info))
+        }
+    }
+
+    fn session(titles: &[&str]) -> (PaneManifest, Vec<TabInfo>) {
+        let panes = (0..)
+            .zip(titles)
+            .map(|(id, title)| PaneInfo {
+                id,
+                title: title.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let tab_info = TabInfo {
+            name: "Tab #1".to_string(),
+            ..Default::default()
+        };
+        let pane_manifest = PaneManifest {
+            panes: HashMap::from([(0, panes)]),
+        };
+        (pane_manifest, vec![tab_info])
+    }
+
+    /// Two tabs named "Tab #1": the first with zsh panes 7 and 5, the second with zsh pane 3.
+    fn same_named_tabs() -> (PaneManifest, Vec<TabInfo>) {
+        let zsh = |id| PaneInfo {
+            id,
+            title: "zsh".to_string(),
+            ..Default::default()
+        };
+        let tab = |position| TabInfo {
+            position,
+            name: "Tab #1".to_string(),
+            ..Default::default()
+        };
+        let pane_manifest = PaneManifest {
+            panes: HashMap::from([(0, vec![zsh(7), zsh(5)]), (1, vec![zsh(3)])]),
+        };
+        (pane_manifest, vec![tab(1), tab(0)])
+    }
+
+    #[test]
+    fn copies_match_the_pane_of_their_original() {
+        let mut persistence = Persistence {
+            pending_bookmarks: vec![bookmark("nvim", 0), bookmark("nvim", 1)],
+            ..Default::default()
+        };
+        let (pane_manifest, tab_infos) = session(&["nvim"]);
+        let matched = persistence.match_pending_bookmarks(&[], &pane_manifest, &tab_infos);
+        let keys: Vec<(u32, u32)> = matched.iter().map(Pane::key).collect();
+        assert_eq!(keys, [(0, 0), (0, 1)]);
+        assert!(persistence.pending_bookmarks.is_empty());
+    }
+
+    #[test]
+    fn each_pane_matches_one_bookmark() {
+        let mut persistence = Persistence {
+            pending_bookmarks: vec![bookmark("zsh", 0), bookmark("zsh", 0)],
+            ..Default::default()
+        };
+        let (pane_manifest, tab_infos) = session(&["zsh", "zsh"]);
+        let matched = persistence.match_pending_bookmarks(&[], &pane_manifest, &tab_infos);
+        let ids: Vec<u32> = matched.iter().map(|p| p.pane_info.id).collect();
+        assert_eq!(ids, [0, 1]);
+
+        // Across two tabs of the same name, no pane is matched twice and the extra bookmark
+        // stays pending.
+        let mut persistence = Persistence {
+            pending_bookmarks: vec![bookmark("zsh", 0); 4],
+            ..Default::default()
+        };
+        let (pane_manifest, tab_infos) = same_named_tabs();
+        let matched = persistence.match_pending_bookmarks(&[], &pane_manifest, &tab_infos);
+        let ids: Vec<u32> = matched.iter().map(|p| p.pane_info.id).collect();
+        assert_eq!(ids, [5, 7, 3]);
+        assert_eq!(persistence.pending_bookmarks.len(), 1);
+    }
+
+    #[test]
+    fn chunks_split_on_char_boundaries() {
+        assert_eq!(chunks("", 4), [""]);
+        assert_eq!(chunks("abcdefgh", 4), ["abcd", "efgh"]);
+        assert_eq!(chunks("abcdé", 5), ["abcd", "é"]);
+        let long = "x".repeat(MAX_ARG_BYTES * 2 + 1);
+        assert!(chunks(&long, MAX_ARG_BYTES)
+            .iter()
+            .all(|c| c.len() <= MAX_ARG_BYTES));
+        assert_eq!(chunks(&long, MAX_ARG_BYTES).concat(), long);
+    }
+
+    fn saved_file() -> BookmarkFile {
+        let mut pinned = bookmark("nvim", 0);
+        pinned.pinned = true;
+        pinned.tag = Some("edit".to_string());
+        pinned.geometry = Some(PaneGeometry {
+            x: 1,
+            y: 2,
+            rows: 30,
+            cols: 80,
+            floating: true,
+        });
+        BookmarkFile {
+            generated_by: Some(GENERATED_BY.to_string()),
+            session: Some("work".to_string()),
+            bookmarks: vec![pinned, bookmark("nvim", 1)],
+            view: Some(ViewPreferences {
+                sort_mode: Some(SortMode::Alpha),
+                collapse_tab_labels: Some(true),
+                scroll: None,
+            }),
+            active_preset: Some("frontend".to_string()),
+        }
+    }
+
+    #[test]
+    fn json_file_round_trips() {
+        let file = saved_file();
+        let contents = PersistFormat::Json.serialize(&file).unwrap();
+        assert_eq!(PersistFormat::Json.deserialize(&contents), Ok(file));
+    }
+
+    #[test]
+    fn toml_file_round_trips() {
+        let file = saved_file();
+        let contents = PersistFormat::Toml.serialize(&file).unwrap();
+        assert_eq!(PersistFormat::Toml.deserialize(&contents), Ok(file));
+    }
+
+    #[test]
+    fn legacy_json_list_is_read() {
+        let contents = r#"[{"tab_name": "Tab #1", "pane_title": "nvim"}]"#;
+        let file = PersistFormat::Json.deserialize(contents).unwrap();
+        assert_eq!(file.bookmarks.len(), 1);
+        assert_eq!(file.bookmarks[0].pane_title, "nvim");
+        assert!(!file.bookmarks[0].pinned);
+        assert_eq!(file.bookmarks[0].copy, 0);
+        assert_eq!(file.view, None);
+    }
+
+    #[test]
+    fn original_bookmark_leaves_out_copy() {
+        let file = BookmarkFile {
+            bookmarks: vec![bookmark("nvim", 0)],
+            ..Default::default()
+        };
+        let contents = PersistFormat::Json.serialize(&file).unwrap();
+        assert!(!contents.contains("copy"));
+    }
+
+    #[test]
+    fn garbage_is_an_error() {
+        assert!(PersistFormat::Json.deserialize("{").is_err());
+        assert!(PersistFormat::Toml.deserialize("bookmarks = 1").is_err());
+    }
+
+    #[test]
+    fn same_named_tabs_match_by_position_then_id() {
+        let mut anywhere = bookmark("zsh", 0);
+        anywhere.tab_position = None;
+        let mut second_tab = bookmark("zsh", 0);
+        second_tab.tab_position = Some(1);
+        let mut persistence = Persistence {
+            pending_bookmarks: vec![anywhere, second_tab],
+            ..Default::default()
+        };
+        let (pane_manifest, tab_infos) = same_named_tabs();
+        let matched = persistence.match_pending_bookmarks(&[], &pane_manifest, &tab_infos);
+        let keys: Vec<(usize, u32)> = matched
+            .iter()
+            .map(|p| (p.tab_info.position, p.pane_info.id))
+            .collect();
+        assert_eq!(keys, [(0, 5), (1, 3)]);
+    }
+
+    #[test]
+    fn file_contents_rejects_large_or_binary_files() {
+        assert_eq!(file_contents(b"[]"), Ok("[]"));
+        let large = vec![b' '; MAX_FILE_BYTES + 1];
+        assert!(file_contents(&large).is_err());
+        assert!(file_contents(&[b'[', 0xff, b']']).is_err());
+    }
+
+    #[test]
+    fn presets_are_named_files_in_the_data_dir() {
+        let persistence = Persistence::default();
+        assert_eq!(
+            persistence.preset_path("web").as_deref(),
+            Some("~/.local/share/zellij-harpoon/presets/web.json")
+        );
+        for name in ["", ".hidden", "../web", "a/b", "a\\b"] {
+            assert_eq!(persistence.preset_path(name), None);
+        }
+    }
+
+    #[test]
+    fn active_preset_is_restored_if_valid() {
+        let mut persistence = Persistence::default();
+        let contents = PersistFormat::Json.serialize(&saved_file()).unwrap();
+        persistence.on_load_command(Some(0), contents.as_bytes());
+        assert_eq!(persistence.active_preset.as_deref(), Some("frontend"));
+
+        let file = BookmarkFile {
+            active_preset: Some("../frontend".to_string()),
+            ..saved_file()
+        };
+        let contents = PersistFormat::Json.serialize(&file).unwrap();
+        persistence.on_load_command(Some(0), contents.as_bytes());
+        assert_eq!(persistence.active_preset, None);
+    }
+
+    #[test]
+    fn corrupt_file_is_backed_up_and_reset() {
+        let mut persistence = Persistence {
+            pending_bookmarks: vec![bookmark("nvim", 0)],
+            ..Default::default()
+        };
+        let notice = persistence.on_load_command(Some(0), b"{ \"bookmarks\": [");
+        assert_eq!(
+            notice.as_deref(),
+            Some("Recovered from corrupt bookmark file (backup saved)")
+        );
+        assert!(persistence.pending_bookmarks.is_empty());
+        assert!(persistence.backup_pending());
+
+        let mut persistence = Persistence::default();
+        assert!(persistence.on_load_command(Some(0), &[0xff]).is_some());
+        assert!(persistence.backup_pending());
+    }
+
+    #[test]
+    fn base_list_goes_below_the_session_without_duplicates() {
+        let mut persistence = Persistence {
+            base_list_path: Some("~/team/harpoon.json".to_string()),
+            pending_bookmarks: vec![bookmark("nvim", 0)],
+            ..Default::default()
+        };
+        let live = Pane::new(
+            PaneInfo {
+                title: "htop".to_string(),
+                ..Default::default()
+            },
+            TabInfo {
+                name: "Tab #1".to_string(),
+                ..Default::default()
+            },
+        );
+        let base = BookmarkFile {
+            bookmarks: ["htop", "nvim", "logs"].map(|t| bookmark(t, 0)).to_vec(),
+            ..Default::default()
+        };
+        let contents = PersistFormat::Json.serialize(&base).unwrap();
+        let notice = persistence.on_base_load_command(Some(0), contents.as_bytes(), &[live]);
+        assert_eq!(notice, None);
+        let pending: Vec<(&str, bool)> = (persistence.pending_bookmarks.iter())
+            .map(|b| (b.pane_title.as_str(), b.base))
+            .collect();
+        assert_eq!(pending, [("nvim", false), ("logs", true)]);
+    }
+
+    #[test]
+    fn note_round_trips() {
+        let mut noted = bookmark("nvim", 0);
+        noted.note = Some("runs the tests".to_string());
+        let file = BookmarkFile {
+            bookmarks: vec![noted],
+            ..Default::default()
+        };
+        for format in [PersistFormat::Json, PersistFormat::Toml] {
+            let contents = format.serialize(&file).unwrap();
+            let mut persistence = Persistence {
+                pending_bookmarks: format.deserialize(&contents).unwrap().bookmarks,
+                ..Default::default()
+            };
+            let (pane_manifest, tab_infos) = session(&["nvim"]);
+            let matched = persistence.match_pending_bookmarks(&[], &pane_manifest, &tab_infos);
+            assert_eq!(matched[0].note.as_deref(), Some("runs the tests"));
+        }
+    }
+}